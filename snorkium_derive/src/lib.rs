@@ -0,0 +1,233 @@
+//! Custom derives for snorkium.
+//!
+//! `#[derive(Component)]` implements `snorkium::ecs::Component`.
+//! The storage defaults to `DefaultStorage`, and can be chosen with an attribute
+//! naming any storage type which is generic over the component and in scope:
+//!
+//! ```ignore
+//! #[derive(Component)]
+//! #[storage(NullStorage)]
+//! struct Boss;
+//! ```
+//!
+//! Reflection glue (`snorkium::ecs::reflect::Reflect`) can be generated as well,
+//! which data formats and tools use to read and write component fields:
+//!
+//! ```ignore
+//! #[derive(Component)]
+//! #[component(reflect)]
+//! struct Position { x: f32, y: f32 }
+//! ```
+//!
+//! `#[component(serialize)]` implements `snorkium::ecs::SerializeComponent`
+//! so the component can be saved with the world, and implies `reflect`.
+//! `#[component(clone)]` lets `World::clone_entity` copy the component,
+//! which must then be `Clone`, and `#[component(hash)]` includes a `Hash`
+//! component in `World::state_hash`, which panics on components without it.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use syn::{Data, DeriveInput, Fields, Path};
+use syn::ext::IdentExt;
+
+#[proc_macro_derive(Component, attributes(storage, component))]
+pub fn derive_component(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    match expand(&ast) {
+        Ok(expanded) => expanded.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let options = Options::parse(ast)?;
+
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let storage = match options.storage {
+        Some(ref storage) => quote! { #storage<Self> },
+        None => quote! { _snorkium::ecs::DefaultStorage<Self> },
+    };
+
+    let reflect = if options.reflect || options.serialize { reflect_impl(ast)? } else { quote! {} };
+    let serialize = if options.serialize {
+        quote! {
+            impl #impl_generics _snorkium::ecs::SerializeComponent for #name #ty_generics #where_clause {}
+        }
+    } else {
+        quote! {}
+    };
+
+    let duplicate = if options.clone {
+        quote! {
+            fn duplicate(&self) -> Option<Self> { Some(Clone::clone(self)) }
+        }
+    } else {
+        quote! {}
+    };
+
+    // `dyn` is spelled out so the expansion also compiles in crates on later editions.
+    // it's followed by an imported name since `dyn ::path` is a path on 2015.
+    let (hasher, hash_state) = if options.hash {
+        (quote! {
+            use std::hash::Hasher as _SnorkiumHasher;
+        }, quote! {
+            fn hash_state(&self, state: &mut dyn _SnorkiumHasher) -> bool {
+                ::std::hash::Hash::hash(self, &mut &mut *state);
+                true
+            }
+        })
+    } else {
+        (quote! {}, quote! {})
+    };
+
+    // the extern crate is scoped to an unnamed constant so the expansion works
+    // no matter how (or whether) the user has imported snorkium.
+    Ok(quote! {
+        #[allow(unused_attributes, unused_qualifications)]
+        const _: () = {
+            extern crate snorkium as _snorkium;
+            #hasher
+
+            impl #impl_generics _snorkium::ecs::Component for #name #ty_generics #where_clause {
+                type Storage = #storage;
+                #duplicate
+                #hash_state
+            }
+
+            #reflect
+            #serialize
+        };
+    })
+}
+
+// options given through attributes.
+struct Options {
+    storage: Option<Path>,
+    reflect: bool,
+    serialize: bool,
+    clone: bool,
+    hash: bool,
+}
+
+impl Options {
+    fn parse(ast: &DeriveInput) -> syn::Result<Self> {
+        let mut options = Options {
+            storage: None,
+            reflect: false,
+            serialize: false,
+            clone: false,
+            hash: false,
+        };
+
+        for attr in &ast.attrs {
+            if attr.path().is_ident("storage") {
+                let storage = attr.parse_args::<Path>().map_err(|err| {
+                    syn::Error::new(err.span(), "expected a single storage type, like `#[storage(HashMapStorage)]`")
+                })?;
+                options.storage = Some(storage);
+            } else if attr.path().is_ident("component") {
+                attr.parse_nested_meta(|meta| {
+                    let flag = if meta.path.is_ident("reflect") {
+                        &mut options.reflect
+                    } else if meta.path.is_ident("serialize") {
+                        &mut options.serialize
+                    } else if meta.path.is_ident("clone") {
+                        &mut options.clone
+                    } else if meta.path.is_ident("hash") {
+                        &mut options.hash
+                    } else {
+                        return Err(meta.error("unknown `component` option, expected `reflect`, `serialize`, `clone` or `hash`"));
+                    };
+
+                    *flag = true;
+                    Ok(())
+                })?;
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+fn reflect_impl(ast: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &ast.ident;
+    let name_str = name.unraw().to_string();
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+
+    let fields = match ast.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new_spanned(name, "`#[component(reflect)]` is only supported on structs")),
+    };
+
+    let (to_value, from_value) = match *fields {
+        Fields::Named(ref fields) => {
+            let idents: Vec<_> = fields.named.iter().map(|f| f.ident.clone().unwrap()).collect();
+            let names: Vec<_> = idents.iter().map(|i| i.unraw().to_string()).collect();
+
+            (quote! {
+                _snorkium::ecs::reflect::Value::Struct(vec![
+                    #((#names.to_owned(), _snorkium::ecs::reflect::Reflect::to_value(&self.#idents)),)*
+                ])
+            }, quote! {
+                let mut fields = _snorkium::ecs::reflect::Fields::new(value)?;
+                let out = #name {
+                    #(#idents: fields.take(#names)?,)*
+                };
+                fields.finish()?;
+                Ok(out)
+            })
+        }
+        Fields::Unnamed(ref fields) => {
+            let indices: Vec<_> = (0..fields.unnamed.len()).map(syn::Index::from).collect();
+            let len = fields.unnamed.len();
+            let elements: Vec<_> = fields.unnamed.iter().map(|_| quote! {
+                _snorkium::ecs::reflect::Reflect::from_value(elements.next().unwrap())?
+            }).collect();
+
+            (quote! {
+                _snorkium::ecs::reflect::Value::Tuple(vec![
+                    #(_snorkium::ecs::reflect::Reflect::to_value(&self.#indices),)*
+                ])
+            }, quote! {
+                let mut elements = _snorkium::ecs::reflect::tuple_elements(value, #len)?;
+                Ok(#name(#(#elements,)*))
+            })
+        }
+        Fields::Unit => {
+            (quote! {
+                _snorkium::ecs::reflect::Value::Unit
+            }, quote! {
+                match value {
+                    _snorkium::ecs::reflect::Value::Unit => Ok(#name),
+                    other => Err(_snorkium::ecs::reflect::ReflectError::Mismatch {
+                        expected: "unit",
+                        found: other.kind(),
+                    }),
+                }
+            })
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics _snorkium::ecs::reflect::Reflect for #name #ty_generics #where_clause {
+            fn type_name() -> &'static str { #name_str }
+
+            fn to_value(&self) -> _snorkium::ecs::reflect::Value {
+                #to_value
+            }
+
+            fn from_value(value: _snorkium::ecs::reflect::Value)
+            -> Result<Self, _snorkium::ecs::reflect::ReflectError> {
+                #from_value
+            }
+        }
+    })
+}
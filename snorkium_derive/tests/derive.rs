@@ -0,0 +1,88 @@
+extern crate snorkium;
+#[macro_use]
+extern crate snorkium_derive;
+
+use std::hash::Hasher;
+
+use snorkium::ecs::{Component, DefaultStorage, EntityManager, SerializeComponent, Storage};
+use snorkium::ecs::determinism::StateHasher;
+use snorkium::ecs::reflect::{Reflect, Value};
+use snorkium::ecs::storage::{HashMapStorage, NullStorage};
+
+#[derive(Component, Debug, PartialEq)]
+#[component(reflect)]
+struct Position {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Component, Debug, PartialEq)]
+#[storage(HashMapStorage)]
+#[component(reflect)]
+struct Name(String);
+
+#[derive(Component, Default)]
+#[storage(NullStorage)]
+struct Boss;
+
+#[derive(Component, Debug, PartialEq, Clone, Hash)]
+#[component(serialize, clone, hash)]
+struct Health(u32);
+
+// field types using newer syntax, like `dyn`, have to parse.
+#[derive(Component)]
+#[storage(HashMapStorage)]
+struct Trigger(Box<dyn Fn(u32) -> bool + Send + Sync>);
+
+// compile-time check that the storage association is what was asked for.
+fn assert_storage<T: Component<Storage=S>, S>() {}
+
+fn assert_serialize<T: SerializeComponent>() {}
+
+#[test]
+fn storage_attribute() {
+    assert_storage::<Position, DefaultStorage<Position>>();
+    assert_storage::<Name, HashMapStorage<Name>>();
+    assert_storage::<Boss, NullStorage<Boss>>();
+    assert_storage::<Trigger, HashMapStorage<Trigger>>();
+
+    let mut manager = EntityManager::new();
+    let e = manager.next();
+    let mut storage = <Boss as Component>::Storage::default();
+    storage.set(manager.verify(e).unwrap(), Boss);
+    assert!(storage.has(manager.verify(e).unwrap()));
+
+    let mut storage = <Trigger as Component>::Storage::default();
+    storage.set(manager.verify(e).unwrap(), Trigger(Box::new(|x| x > 2)));
+    assert!((storage.get(manager.verify(e).unwrap()).unwrap().0)(3));
+}
+
+#[test]
+fn reflection() {
+    assert_eq!(Position::type_name(), "Position");
+
+    let pos = Position { x: 1.0, y: -2.0 };
+    let value = pos.to_value();
+    assert_eq!(value, Value::Struct(vec![
+        ("x".to_owned(), Value::Float(1.0)),
+        ("y".to_owned(), Value::Float(-2.0)),
+    ]));
+    assert_eq!(Position::from_value(value).unwrap(), pos);
+    assert!(Position::from_value(Value::Struct(vec![])).is_err());
+
+    let name = Name("snork".to_owned());
+    assert_eq!(Name::from_value(name.to_value()).unwrap(), name);
+
+    assert_serialize::<Health>();
+    assert_eq!(Health::from_value(Health(3).to_value()).unwrap(), Health(3));
+    assert_eq!(Health(3).duplicate(), Some(Health(3)));
+    assert_eq!(Position { x: 0.0, y: 0.0 }.duplicate(), None);
+
+    let hash = |h: Health| {
+        let mut state = StateHasher::new();
+        assert!(h.hash_state(&mut state));
+        state.finish()
+    };
+    assert_eq!(hash(Health(3)), hash(Health(3)));
+    assert!(hash(Health(3)) != hash(Health(4)));
+}
@@ -0,0 +1,286 @@
+//! Driving a world through time.
+//!
+//! An `App` owns a world and two schedulers. The fixed-update scheduler runs at
+//! a constant rate, as many times per frame as the time passed calls for, which
+//! keeps the simulation independent of the frame rate. The frame scheduler runs
+//! once per frame afterwards, for work such as rendering and interpolation.
+//!
+//! The `Time` resource tells systems the timing of the update they're in. Time is
+//! advanced either from the real clock with `update` and `run`, or by hand with
+//! `advance`, which lets tests step the simulation without waiting.
+
+use std::time::{Duration, Instant};
+
+use ecs::{System, World};
+use ecs::schedule::Scheduler;
+use ecs::set::Set;
+
+// fixed updates per second unless configured otherwise.
+const DEFAULT_RATE: f64 = 60.0;
+// fixed updates per frame before falling behind is given up on.
+const DEFAULT_MAX_STEPS: u32 = 8;
+
+/// Timing of the current update, available to systems as a resource.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Time {
+    delta: f64,
+    elapsed: f64,
+    alpha: f64,
+    fixed_delta: f64,
+    ticks: u64,
+}
+
+impl Time {
+    /// Seconds since the last update of the same kind: the fixed timestep
+    /// during fixed updates, and the frame time during frame updates.
+    pub fn delta(&self) -> f64 {
+        self.delta
+    }
+
+    /// Seconds of simulated time. During frame updates this includes
+    /// the time not yet consumed by a fixed update.
+    pub fn elapsed(&self) -> f64 {
+        self.elapsed
+    }
+
+    /// How far the frame is between the last fixed update and the next,
+    /// from 0 to 1, for interpolating between them when rendering.
+    pub fn alpha(&self) -> f64 {
+        self.alpha
+    }
+
+    /// The length of a fixed update, in seconds.
+    pub fn fixed_delta(&self) -> f64 {
+        self.fixed_delta
+    }
+
+    /// The number of fixed updates run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+/// A world with the schedulers that update it.
+pub struct App<S: Set> {
+    world: World<S>,
+    fixed: Scheduler<S>,
+    frame: Scheduler<S>,
+    time: Time,
+    // the fixed timestep, and the time passed which hasn't been consumed by
+    // fixed updates, both in nanoseconds so that they add up exactly.
+    step: u64,
+    accumulator: u64,
+    max_steps: u32,
+    last: Option<Instant>,
+}
+
+impl<S: Set> App<S> {
+    /// An app running a world with no systems, at 60 fixed updates per second.
+    pub fn new(world: World<S>) -> Self {
+        let mut app = App {
+            world: world,
+            fixed: Scheduler::new(),
+            frame: Scheduler::new(),
+            time: Time {
+                delta: 0.0,
+                elapsed: 0.0,
+                alpha: 0.0,
+                fixed_delta: seconds(nanos_per_update(DEFAULT_RATE)),
+                ticks: 0,
+            },
+            step: nanos_per_update(DEFAULT_RATE),
+            accumulator: 0,
+            max_steps: DEFAULT_MAX_STEPS,
+            last: None,
+        };
+
+        app.publish_time();
+        app
+    }
+
+    /// Set the number of fixed updates per second.
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        assert!(rate > 0.0, "the fixed update rate must be positive");
+        self.step = nanos_per_update(rate);
+        self.time.fixed_delta = seconds(self.step);
+        self.publish_time();
+        self
+    }
+
+    /// Set how many fixed updates may run in one frame. Time beyond that is
+    /// dropped, so that a slow frame doesn't cause ever more updates.
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// Add a system to the fixed update.
+    pub fn with_fixed_system<T: System + 'static>(mut self, name: &str, system: T) -> Self {
+        self.fixed.add(name, system);
+        self
+    }
+
+    /// Add a system to the frame update.
+    pub fn with_frame_system<T: System + 'static>(mut self, name: &str, system: T) -> Self {
+        self.frame.add(name, system);
+        self
+    }
+
+    /// The world.
+    pub fn world(&self) -> &World<S> {
+        &self.world
+    }
+
+    /// Mutable access to the world.
+    pub fn world_mut(&mut self) -> &mut World<S> {
+        &mut self.world
+    }
+
+    /// The scheduler for fixed updates.
+    pub fn fixed_scheduler(&mut self) -> &mut Scheduler<S> {
+        &mut self.fixed
+    }
+
+    /// The scheduler for frame updates.
+    pub fn frame_scheduler(&mut self) -> &mut Scheduler<S> {
+        &mut self.frame
+    }
+
+    /// The timing of the last update.
+    pub fn time(&self) -> Time {
+        self.time
+    }
+
+    /// Run a frame covering the given time, without looking at the clock.
+    /// Returns the number of fixed updates run.
+    pub fn advance(&mut self, delta: Duration) -> u32 {
+        let step = self.step;
+        self.accumulator += nanos(delta);
+
+        let mut steps = 0;
+        while self.accumulator >= step {
+            if steps == self.max_steps {
+                self.accumulator = 0;
+                break
+            }
+
+            self.accumulator -= step;
+            self.time.delta = seconds(step);
+            self.time.elapsed = seconds(self.time.ticks * step);
+            self.time.alpha = 0.0;
+            self.publish_time();
+            self.fixed.run(&mut self.world);
+
+            self.time.ticks += 1;
+            steps += 1;
+        }
+
+        self.time.delta = seconds(nanos(delta));
+        self.time.elapsed = seconds(self.time.ticks * step + self.accumulator);
+        self.time.alpha = self.accumulator as f64 / step as f64;
+        self.publish_time();
+        self.frame.run(&mut self.world);
+
+        steps
+    }
+
+    /// Run a frame covering the time since the last one by the clock.
+    /// The first frame covers no time. Returns the number of fixed updates run.
+    pub fn update(&mut self) -> u32 {
+        let now = Instant::now();
+        let delta = self.last.map_or(Duration::from_secs(0), |last| now.duration_since(last));
+        self.last = Some(now);
+        self.advance(delta)
+    }
+
+    /// Run frames by the clock until the function returns false.
+    pub fn run<F: FnMut(&mut App<S>) -> bool>(&mut self, mut keep_going: F) {
+        while keep_going(self) {
+            self.update();
+        }
+    }
+
+    // put the current timing into the world for systems to read.
+    fn publish_time(&mut self) {
+        self.world.resources_mut().insert(self.time);
+    }
+}
+
+fn nanos_per_update(rate: f64) -> u64 {
+    (1e9 / rate).round().max(1.0) as u64
+}
+
+fn nanos(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000_000 + duration.subsec_nanos() as u64
+}
+
+fn seconds(nanos: u64) -> f64 {
+    nanos as f64 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use ecs::{Component, DefaultStorage, WorldHandle};
+
+    struct Position;
+    impl Component for Position { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Position }
+    }
+
+    // logs the time it sees, tagged with its update.
+    struct Log(&'static str, Arc<Mutex<Vec<(&'static str, Time)>>>);
+
+    impl System for Log {
+        fn process<'a, S: 'a + Set>(&mut self, wh: WorldHandle<'a, S>) {
+            let time = *wh.resource::<Time>().unwrap();
+            self.1.lock().unwrap().push((self.0, time));
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn fixed_steps_and_alpha() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut app = App::new(World::new(TestSet::new()))
+            .with_rate(10.0)
+            .with_max_steps(3)
+            .with_fixed_system("fixed", Log("fixed", log.clone()))
+            .with_frame_system("frame", Log("frame", log.clone()));
+
+        assert_eq!(app.advance(ms(250)), 2);
+        {
+            let log = log.lock().unwrap();
+            let kinds: Vec<_> = log.iter().map(|&(kind, _)| kind).collect();
+            assert_eq!(kinds, vec!["fixed", "fixed", "frame"]);
+            assert!((log[1].1.elapsed() - 0.1).abs() < 1e-9);
+            assert!((log[1].1.delta() - 0.1).abs() < 1e-9);
+
+            let frame = log[2].1;
+            assert!((frame.delta() - 0.25).abs() < 1e-9);
+            assert!((frame.alpha() - 0.5).abs() < 1e-9);
+            assert!((frame.elapsed() - 0.25).abs() < 1e-9);
+            assert_eq!(frame.ticks(), 2);
+        }
+
+        // the leftover time carries into the next frame.
+        assert_eq!(app.advance(ms(50)), 1);
+        assert!(app.time().alpha().abs() < 1e-9);
+
+        // a long frame runs at most the maximum, and the rest is dropped.
+        assert_eq!(app.advance(ms(10_000)), 3);
+        assert_eq!(app.time().ticks(), 6);
+        assert_eq!(app.advance(ms(0)), 0);
+
+        let mut frames = 0;
+        app.run(|_| { frames += 1; frames < 3 });
+        assert_eq!(frames, 3);
+    }
+}
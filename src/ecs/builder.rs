@@ -0,0 +1,193 @@
+//! Spawning entities with many components at once.
+
+use super::*;
+use super::set::Set;
+
+/// A group of components which are inserted together.
+///
+/// This is implemented for tuples of components, and for the
+/// `BundleEntry` lists built up by `EntityBuilder`.
+pub trait Bundle: Sized {
+    /// Insert the components of every bundle for its entity.
+    ///
+    /// Each component's storage is accessed once for the whole batch,
+    /// rather than once per entity. Dead entities are skipped.
+    fn insert_batch<S: Set>(set: &mut S, entities: &EntityManager, items: Vec<(Entity, Self)>);
+}
+
+/// An entry in the list of components held by an `EntityBuilder`.
+pub struct BundleEntry<T: Component, P: Bundle> {
+    data: T,
+    parent: P,
+}
+
+impl<T: Component, P: Bundle> Bundle for BundleEntry<T, P> {
+    fn insert_batch<S: Set>(set: &mut S, entities: &EntityManager, items: Vec<(Entity, Self)>) {
+        let (data, parents): (Vec<_>, Vec<_>) = items.into_iter()
+            .map(|(e, entry)| ((e, entry.data), (e, entry.parent)))
+            .unzip();
+
+        insert_one(set, entities, data);
+        P::insert_batch(set, entities, parents);
+    }
+}
+
+// set the given component for each entity, accessing the storage once.
+fn insert_one<T: Component, S: Set>(set: &mut S, entities: &EntityManager, items: Vec<(Entity, T)>) {
+    let storage = set.get_storage_mut::<T>();
+    for (e, data) in items {
+        if let Some(e) = entities.verify(e) {
+            storage.set(e, data);
+        }
+    }
+}
+
+impl Bundle for () {
+    fn insert_batch<S: Set>(_: &mut S, _: &EntityManager, _: Vec<(Entity, Self)>) {}
+}
+
+macro_rules! bundle_impl {
+    ($f_id: ident $($id: ident)*) => {
+        impl<$f_id: Component, $($id: Component,)*> Bundle for ($f_id, $($id,)*) {
+            #[allow(non_snake_case)]
+            fn insert_batch<SET: Set>(set: &mut SET, entities: &EntityManager, items: Vec<(Entity, Self)>) {
+                let (first, rest): (Vec<_>, Vec<_>) = items.into_iter()
+                    .map(|(e, ($f_id, $($id,)*))| ((e, $f_id), (e, ($($id,)*))))
+                    .unzip();
+
+                insert_one(set, entities, first);
+                <($($id,)*) as Bundle>::insert_batch(set, entities, rest);
+            }
+        }
+
+        bundle_impl!($($id)*);
+    };
+
+    () => {};
+}
+
+bundle_impl!(A B C D E F G H I J K);
+
+/// Builds up a list of components for a new entity.
+///
+/// See `World::build_entity()`.
+pub struct EntityBuilder<'a, S: 'a + Set, B: Bundle> {
+    world: &'a mut World<S>,
+    bundle: B,
+}
+
+impl<'a, S: 'a + Set> EntityBuilder<'a, S, ()> {
+    /// Create a builder for an entity in the given world.
+    /// Use of `World::build_entity()` is advised over this.
+    pub fn new(world: &'a mut World<S>) -> Self {
+        EntityBuilder {
+            world: world,
+            bundle: (),
+        }
+    }
+}
+
+impl<'a, S: 'a + Set, B: Bundle> EntityBuilder<'a, S, B> {
+    /// Add a component to the entity.
+    ///
+    /// Adding a component more than once will keep the first value.
+    pub fn with<T: Component>(self, data: T) -> EntityBuilder<'a, S, BundleEntry<T, B>> {
+        EntityBuilder {
+            world: self.world,
+            bundle: BundleEntry {
+                data: data,
+                parent: self.bundle,
+            },
+        }
+    }
+
+    /// Create the entity along with all of its components.
+    pub fn spawn(self) -> Entity {
+        let e = self.world.entities.next();
+        B::insert_batch(&mut self.world.data, &self.world.entities, vec![(e, self.bundle)]);
+        e
+    }
+}
+
+impl<S: Set> World<S> {
+    /// Start building an entity.
+    ///
+    /// # Examples
+    /// ```
+    /// #[macro_use] extern crate snorkium;
+    /// use snorkium::ecs::{Component, DefaultStorage};
+    ///
+    /// struct Position(f32, f32);
+    /// impl Component for Position { type Storage = DefaultStorage<Self>; }
+    /// struct Velocity(f32, f32);
+    /// impl Component for Velocity { type Storage = DefaultStorage<Self>; }
+    ///
+    /// world! {
+    ///     pub GameWorld(GameSet) { Position, Velocity }
+    /// }
+    ///
+    /// # fn main() {
+    /// let mut world = GameWorld::new(GameSet::new());
+    /// let e = world.build_entity()
+    ///     .with(Position(0.0, 0.0))
+    ///     .with(Velocity(1.0, 0.0))
+    ///     .spawn();
+    /// # }
+    /// ```
+    pub fn build_entity(&mut self) -> EntityBuilder<S, ()> {
+        EntityBuilder::new(self)
+    }
+
+    /// Spawn an entity for each bundle of components, returning the
+    /// new entities in order.
+    ///
+    /// Each component's storage is accessed once for the whole batch.
+    pub fn spawn_batch<B, I>(&mut self, bundles: I) -> Vec<Entity>
+    where B: Bundle, I: IntoIterator<Item=B> {
+        let items: Vec<_> = {
+            let entities = &mut self.entities;
+            bundles.into_iter().map(|b| (entities.next(), b)).collect()
+        };
+
+        let spawned = items.iter().map(|&(e, _)| e).collect();
+        B::insert_batch(&mut self.data, &self.entities, items);
+        spawned
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq)]
+    struct Position(f32, f32);
+    impl Component for Position { type Storage = DefaultStorage<Self>; }
+
+    #[derive(Debug, PartialEq)]
+    struct Name(String);
+    impl Component for Name { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Position, Name }
+    }
+
+    #[test]
+    fn build_and_batch() {
+        let mut world = World::new(TestSet::new());
+
+        let e = world.build_entity()
+            .with(Position(1.0, 2.0))
+            .with(Name("snork".to_owned()))
+            .spawn();
+
+        let batch = world.spawn_batch((0..10).map(|i| (Position(i as f32, 0.0), Name(i.to_string()))));
+        assert_eq!(batch.len(), 10);
+
+        let e = world.entities.verify(e).unwrap();
+        assert_eq!(world.data.lock_storage::<Position>().get(e), Some(&Position(1.0, 2.0)));
+
+        let e = world.entities.verify(batch[7]).unwrap();
+        assert_eq!(world.data.lock_storage::<Name>().get(e), Some(&Name("7".to_owned())));
+        assert_eq!(world.data.lock_storage::<Position>().entities().count(), 11);
+    }
+}
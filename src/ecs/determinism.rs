@@ -0,0 +1,212 @@
+//! Deterministic simulation.
+//!
+//! By default, queries visit entities in storage order, which depends on the
+//! history of allocations and removals, and the `Scheduler` runs systems in
+//! parallel. In deterministic mode, queries visit entities in order of id (or in
+//! the order of storages which keep one, like `SortedStorage`) and systems run
+//! one at a time in the order they were added. Given the same inputs, two runs
+//! then make the same changes in the same order.
+//!
+//! `World::state_hash` summarizes the state of every entity, for checking that two
+//! runs (or two peers) agree. Every component of a living entity has to implement
+//! `Component::hash_state` for the world to be hashed. Resources are only included
+//! when inserted with `Resources::insert_hashed`.
+
+use std::hash::Hasher;
+
+use super::*;
+use super::set::Set;
+
+impl<S: Set> World<S> {
+    /// Turn deterministic mode on or off.
+    pub fn set_deterministic(&mut self, deterministic: bool) {
+        self.entities.ordered = deterministic;
+    }
+
+    /// Whether the world is in deterministic mode.
+    pub fn is_deterministic(&self) -> bool {
+        self.entities.ordered
+    }
+
+    /// A hash of every living entity and its components, along with the
+    /// resources inserted with `Resources::insert_hashed`.
+    ///
+    /// This doesn't depend on the order of data within storages, and is the
+    /// same across runs and platforms for the same state.
+    ///
+    /// # Panics
+    /// If a living entity has a component which doesn't implement `Component::hash_state`.
+    pub fn state_hash(&self) -> u64 {
+        let mut state = StateHasher::new();
+        let alive = self.entities.alive();
+
+        state.write_usize(alive.len());
+        for e in &alive {
+            state.write_u32(e.id());
+            state.write_u8(e.gen());
+        }
+
+        self.data.hash_state(&alive, &mut state);
+        self.resources().hash_state(&mut state);
+        state.finish()
+    }
+}
+
+/// A 64-bit FNV-1a hasher.
+///
+/// Unlike the standard library's hasher, its output is fixed, so
+/// hashes can be compared between builds and machines.
+#[derive(Debug, Clone, Copy)]
+pub struct StateHasher(u64);
+
+impl StateHasher {
+    /// A hasher which hasn't been written to.
+    pub fn new() -> Self {
+        StateHasher(0xcbf29ce484222325)
+    }
+}
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        StateHasher::new()
+    }
+}
+
+impl Hasher for StateHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+
+    // integers are written little-endian rather than in native order.
+    fn write_u16(&mut self, i: u16) { self.write_u64(i as u64) }
+    fn write_u32(&mut self, i: u32) { self.write_u64(i as u64) }
+    fn write_usize(&mut self, i: usize) { self.write_u64(i as u64) }
+
+    fn write_u64(&mut self, i: u64) {
+        let bytes = [i as u8, (i >> 8) as u8, (i >> 16) as u8, (i >> 24) as u8,
+                     (i >> 32) as u8, (i >> 40) as u8, (i >> 48) as u8, (i >> 56) as u8];
+        self.write(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::storage::HashMapStorage;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(i32);
+
+    impl Component for Position {
+        type Storage = DefaultStorage<Self>;
+
+        fn hash_state(&self, state: &mut Hasher) -> bool {
+            state.write_i32(self.0);
+            true
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Tag(u8);
+
+    impl Component for Tag {
+        type Storage = HashMapStorage<Self>;
+
+        fn hash_state(&self, state: &mut Hasher) -> bool {
+            state.write_u8(self.0);
+            true
+        }
+    }
+
+    // doesn't implement `hash_state`.
+    struct Cache;
+
+    impl Component for Cache { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Position, Tag, Cache }
+    }
+
+    fn ids(world: &World<TestSet>) -> Vec<u32> {
+        world.handle().query::<(Position,)>().for_each(|e, _| e.id())
+    }
+
+    #[test]
+    fn ordered_queries_and_hash() {
+        // the same entities, with positions set in different orders.
+        let mut a = World::new(TestSet::new());
+        let mut b = World::new(TestSet::new());
+        let ea: Vec<Entity> = (0..4).map(|_| a.entities.next()).collect();
+        let eb: Vec<Entity> = (0..4).map(|_| b.entities.next()).collect();
+        assert_eq!(ea, eb);
+
+        for &e in &ea {
+            let v = a.entities.verify(e).unwrap();
+            a.data.get_storage_mut::<Position>().set(v, Position(e.id() as i32));
+            a.data.get_storage_mut::<Tag>().set(v, Tag(1));
+        }
+        for &e in eb.iter().rev() {
+            let v = b.entities.verify(e).unwrap();
+            b.data.get_storage_mut::<Position>().set(v, Position(e.id() as i32));
+            b.data.get_storage_mut::<Tag>().set(v, Tag(1));
+        }
+
+        assert_eq!(ids(&b), vec![3, 2, 1, 0]);
+        b.set_deterministic(true);
+        assert!(b.is_deterministic());
+        assert_eq!(ids(&b), vec![0, 1, 2, 3]);
+
+        assert_eq!(a.state_hash(), b.state_hash());
+        let v = b.entities.verify(eb[2]).unwrap();
+        b.data.get_storage_mut::<Tag>().set(v, Tag(2));
+        assert!(a.state_hash() != b.state_hash());
+    }
+
+    #[derive(Hash)]
+    struct Gravity(i32);
+
+    #[test]
+    fn hashed_resources() {
+        let mut world = World::new(TestSet::new());
+        world.build_entity().with(Position(1)).spawn();
+        let empty = world.state_hash();
+
+        // resources aren't hashed unless they opt in.
+        world.resources_mut().insert(Gravity(1));
+        assert_eq!(world.state_hash(), empty);
+
+        world.resources_mut().insert_hashed(Gravity(1));
+        let hashed = world.state_hash();
+        assert!(hashed != empty);
+        *world.resources_mut().get_mut::<Gravity>().unwrap() = Gravity(2);
+        assert!(world.state_hash() != hashed);
+
+        world.resources_mut().remove::<Gravity>();
+        assert_eq!(world.state_hash(), empty);
+    }
+
+    #[test]
+    #[should_panic(expected = "Cache")]
+    fn unhashable_components() {
+        let mut world = World::new(TestSet::new());
+        world.build_entity().with(Position(1)).spawn();
+        world.state_hash();
+
+        world.build_entity().with(Cache).spawn();
+        world.state_hash();
+    }
+
+    #[test]
+    fn fnv_is_stable() {
+        let mut state = StateHasher::new();
+        state.write(b"a");
+        assert_eq!(state.finish(), 0xaf63dc4c8601ec8c);
+    }
+}
@@ -0,0 +1,394 @@
+//! Parent-child relationships between entities.
+//!
+//! Relationships are stored in the `Parent` component, which must be in the
+//! world's set. `ParentStorage` indexes the children of each parent, so they
+//! are found however the `Parent` was set: through the methods on `World` here,
+//! an `EntityBuilder`, a scene, or the storage itself. Only the methods on
+//! `World` check for cycles.
+//!
+//! Destroying an entity detaches it from its parent and its children.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hasher;
+use std::mem;
+
+use super::*;
+use super::reflect::{tuple_elements, Reflect, ReflectError, Value};
+use super::set::Set;
+
+/// The parent of an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parent(pub Entity);
+
+impl Component for Parent {
+    type Storage = ParentStorage;
+
+    fn duplicate(&self) -> Option<Self> { Some(*self) }
+
+    fn map_entities(&mut self, map: &mut FnMut(Entity) -> Entity) {
+        self.0 = map(self.0);
+    }
+
+    fn hash_state(&self, state: &mut Hasher) -> bool {
+        state.write_u32(self.0.id());
+        state.write_u8(self.0.gen());
+        true
+    }
+}
+
+impl Reflect for Parent {
+    fn type_name() -> &'static str { "Parent" }
+
+    fn to_value(&self) -> Value {
+        Value::Tuple(vec![self.0.to_value()])
+    }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        let mut elements = tuple_elements(value, 1)?;
+        Ok(Parent(Reflect::from_value(elements.next().unwrap())?))
+    }
+}
+
+impl SerializeComponent for Parent {}
+
+/// Storage for `Parent`, indexed by parent so that the children of an entity
+/// can be found, and destroying an entity removes the `Parent` of each of its children.
+pub struct ParentStorage {
+    parents: HashMap<Entity, Parent>,
+    // the children with each parent.
+    children: HashMap<Entity, Vec<Entity>>,
+    // entities handed out mutably, left out of the index until the next mutable access.
+    pending: Vec<Entity>,
+}
+
+impl ParentStorage {
+    /// Create a new, empty storage.
+    pub fn new() -> Self {
+        ParentStorage {
+            parents: HashMap::new(),
+            children: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    fn reindex(&mut self) {
+        for child in mem::take(&mut self.pending) {
+            if let Some(&Parent(parent)) = self.parents.get(&child) {
+                self.children.entry(parent).or_default().push(child);
+            }
+        }
+    }
+
+    // remove the child from the parent's index entry.
+    fn unindex(&mut self, child: Entity, parent: Entity) {
+        let empty = match self.children.get_mut(&parent) {
+            Some(children) => {
+                children.retain(|&e| e != child);
+                children.is_empty()
+            }
+            None => false,
+        };
+
+        if empty { self.children.remove(&parent); }
+    }
+
+    /// The children of an entity, in the order they were attached.
+    ///
+    /// Children whose `Parent` was handed out mutably are listed last
+    /// until the next mutable access to the storage.
+    pub fn children(&self, parent: Entity) -> Vec<Entity> {
+        let mut out = self.children.get(&parent).cloned().unwrap_or_default();
+        out.extend(self.pending.iter().cloned().filter(|c| self.parents.get(c) == Some(&Parent(parent))));
+        out
+    }
+
+    fn take(&mut self, child: Entity) -> Option<Parent> {
+        let parent = self.parents.remove(&child);
+        if let Some(Parent(parent)) = parent { self.unindex(child, parent) }
+        parent
+    }
+}
+
+impl Default for ParentStorage {
+    fn default() -> Self {
+        ParentStorage::new()
+    }
+}
+
+impl Storage<Parent> for ParentStorage {
+    fn set(&mut self, e: VerifiedEntity, data: Parent) {
+        self.reindex();
+
+        let child = e.entity();
+        self.take(child);
+        self.children.entry(data.0).or_default().push(child);
+        self.parents.insert(child, data);
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.parents.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&Parent> {
+        self.parents.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut Parent> {
+        self.reindex();
+
+        let child = e.entity();
+        let parent = match self.parents.get(&child) {
+            Some(&Parent(parent)) => parent,
+            None => return None,
+        };
+
+        // the caller may change the parent, so the child is indexed again later.
+        self.unindex(child, parent);
+        self.pending.push(child);
+        self.parents.get_mut(&child)
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<Parent> {
+        self.reindex();
+        self.take(e.entity())
+    }
+
+    /// Removes the entity's parent, and the parent of each of its children.
+    fn destroy(&mut self, e: Entity) {
+        self.reindex();
+        self.take(e);
+
+        for child in self.children.remove(&e).unwrap_or_default() {
+            self.parents.remove(&child);
+        }
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.parents.keys().cloned())
+    }
+}
+
+/// An error when changing the hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HierarchyError {
+    /// One of the entities was dead.
+    DeadEntity(Entity),
+    /// The new parent is the child itself or one of its descendants.
+    Cycle,
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HierarchyError::DeadEntity(e) => write!(f, "entity {:?} is dead", e),
+            HierarchyError::Cycle => write!(f, "an entity can't be its own ancestor"),
+        }
+    }
+}
+
+impl Error for HierarchyError {
+    fn description(&self) -> &str {
+        match *self {
+            HierarchyError::DeadEntity(_) => "dead entity",
+            HierarchyError::Cycle => "hierarchy cycle",
+        }
+    }
+}
+
+impl<S: Set> World<S> {
+    /// Attach a child to a parent, detaching it from its previous parent if any.
+    pub fn set_parent(&mut self, child: Entity, parent: Entity) -> Result<(), HierarchyError> {
+        for &e in &[child, parent] {
+            if !self.entities.is_alive(e) { return Err(HierarchyError::DeadEntity(e)) }
+        }
+
+        if child == parent || self.ancestors(parent).contains(&child) {
+            return Err(HierarchyError::Cycle)
+        }
+
+        let child = self.entities.verify(child).unwrap();
+        self.data.get_storage_mut::<Parent>().set(child, Parent(parent));
+        Ok(())
+    }
+
+    /// Detach an entity from its parent, returning the parent if it had one.
+    pub fn remove_parent(&mut self, child: Entity) -> Option<Entity> {
+        let child = self.entities.verify(child)?;
+        self.data.get_storage_mut::<Parent>().remove(child).map(|p| p.0)
+    }
+
+    /// The parent of an entity, if it has one.
+    pub fn parent(&self, e: Entity) -> Option<Entity> {
+        let e = self.entities.verify(e)?;
+        self.data.lock_storage::<Parent>().get(e).map(|p| p.0)
+    }
+
+    /// The living children of an entity.
+    pub fn children(&self, e: Entity) -> Vec<Entity> {
+        if !self.entities.is_alive(e) { return Vec::new() }
+
+        let mut children = self.data.lock_storage::<Parent>().children(e);
+        children.retain(|&c| self.entities.is_alive(c));
+        children
+    }
+
+    /// All descendants of an entity, depth-first with parents before their children.
+    pub fn descendants(&self, e: Entity) -> Vec<Entity> {
+        let parents = self.data.lock_storage::<Parent>();
+        let mut out = Vec::new();
+        let mut stack = vec![e];
+
+        while let Some(current) = stack.pop() {
+            if current != e { out.push(current) }
+            if !self.entities.is_alive(current) { continue }
+
+            // pushed in reverse so that they're visited in order.
+            let children = parents.children(current);
+            stack.extend(children.into_iter().rev().filter(|&c| self.entities.is_alive(c)));
+        }
+
+        out
+    }
+
+    /// All ancestors of an entity, starting with its parent.
+    pub fn ancestors(&self, e: Entity) -> Vec<Entity> {
+        let parents = self.data.lock_storage::<Parent>();
+        let mut out = Vec::new();
+        let mut current = e;
+
+        while let Some(e) = self.entities.verify(current) {
+            match parents.get(e) {
+                Some(&Parent(parent)) if self.entities.is_alive(parent) => {
+                    out.push(parent);
+                    current = parent;
+                }
+                _ => break,
+            }
+        }
+
+        out
+    }
+
+    /// Destroy an entity along with all of its descendants.
+    ///
+    /// The entity is detached from its parent first.
+    pub fn despawn_recursive(&mut self, e: Entity) {
+        if !self.entities.is_alive(e) { return }
+
+        self.remove_parent(e);
+        let descendants = self.descendants(e);
+
+        self.despawn(e);
+        for d in descendants {
+            self.despawn(d);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Name(&'static str);
+    impl Component for Name { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Name, Parent }
+    }
+
+    #[test]
+    fn hierarchy() {
+        let mut world = World::new(TestSet::new());
+        let spawn = |world: &mut World<TestSet>, name| world.build_entity().with(Name(name)).spawn();
+
+        let root = spawn(&mut world, "root");
+        let a = spawn(&mut world, "a");
+        let b = spawn(&mut world, "b");
+        let a1 = spawn(&mut world, "a1");
+        let other = spawn(&mut world, "other");
+
+        world.set_parent(a, root).unwrap();
+        world.set_parent(b, root).unwrap();
+        world.set_parent(a1, a).unwrap();
+
+        assert_eq!(world.children(root), vec![a, b]);
+        assert_eq!(world.descendants(root), vec![a, a1, b]);
+        assert_eq!(world.ancestors(a1), vec![a, root]);
+        assert_eq!(world.set_parent(root, a1), Err(HierarchyError::Cycle));
+
+        // reparenting keeps both sides consistent.
+        world.set_parent(a, other).unwrap();
+        assert_eq!(world.children(root), vec![b]);
+        assert_eq!(world.parent(a), Some(other));
+
+        world.despawn_recursive(other);
+        for &e in &[other, a, a1] {
+            assert!(!world.entities.is_alive(e));
+        }
+
+        assert!(world.entities.is_alive(b));
+        assert_eq!(world.data.lock_storage::<Name>().entities().count(), 2);
+        assert_eq!(world.data.lock_storage::<Name>().get(world.entities.verify(b).unwrap()).unwrap().0, "b");
+        assert_eq!(world.descendants(root), vec![b]);
+    }
+
+    #[test]
+    fn despawn_detaches() {
+        let mut world = World::new(TestSet::new());
+        let root = world.build_entity().spawn();
+        let a = world.build_entity().spawn();
+        let b = world.build_entity().spawn();
+        let a1 = world.build_entity().spawn();
+        world.set_parent(a, root).unwrap();
+        world.set_parent(b, root).unwrap();
+        world.set_parent(a1, a).unwrap();
+
+        // a plain despawn of a child takes it out of its parent's children.
+        world.despawn(b);
+        assert_eq!(world.data.lock_storage::<Parent>().children(root), vec![a]);
+
+        // and of a parent, leaves its children without one.
+        world.despawn(root);
+        let a_v = world.entities.verify(a).unwrap();
+        assert!(world.data.lock_storage::<Parent>().get(a_v).is_none());
+        assert_eq!(world.parent(a1), Some(a));
+
+        world.despawn(a1);
+        assert!(world.data.lock_storage::<Parent>().children(a).is_empty());
+        assert_eq!(world.data.lock_storage::<Parent>().entities().count(), 0);
+    }
+
+    #[test]
+    fn parents_set_outside_of_world() {
+        let mut world = World::new(TestSet::new());
+        let root = world.build_entity().spawn();
+        let a = world.build_entity().with(Parent(root)).spawn();
+        let b = world.build_entity().spawn();
+        let a1 = world.build_entity().with(Parent(a)).spawn();
+
+        {
+            let b_v = world.entities.verify(b).unwrap();
+            world.data.get_storage_mut::<Parent>().set(b_v, Parent(root));
+        }
+
+        assert_eq!(world.children(root), vec![a, b]);
+        assert_eq!(world.descendants(root), vec![a, a1, b]);
+
+        // changing a parent in place moves the child once the storage is accessed again,
+        // and is visible before then.
+        {
+            let a1_v = world.entities.verify(a1).unwrap();
+            world.data.get_storage_mut::<Parent>().get_mut(a1_v).unwrap().0 = b;
+        }
+
+        assert!(world.children(a).is_empty());
+        assert_eq!(world.children(b), vec![a1]);
+
+        world.despawn_recursive(root);
+        for &e in &[root, a, b, a1] {
+            assert!(!world.entities.is_alive(e));
+        }
+    }
+}
@@ -0,0 +1,174 @@
+//! Macros for declaring component sets and worlds.
+
+/// Declare a named `Set` type holding storage for a list of components.
+///
+/// Components are listed in order, each optionally followed by `=>` and an
+/// expression creating its storage. Components without one use the default
+/// storage for their type.
+///
+/// This generates the struct, a `new()` constructor, a `Default` implementation,
+/// and the `Set` implementation, so the type can be named in system code rather than
+/// spelling out the nested `SetEntry` type.
+///
+/// # Examples
+/// ```
+/// #[macro_use] extern crate snorkium;
+/// use snorkium::ecs::{Component, DefaultStorage};
+/// use snorkium::ecs::storage::HashMapStorage;
+///
+/// struct Position(f32, f32);
+/// impl Component for Position { type Storage = DefaultStorage<Self>; }
+///
+/// struct Name(String);
+/// impl Component for Name { type Storage = HashMapStorage<Self>; }
+///
+/// component_set! {
+///     pub struct GameSet {
+///         Position,
+///         Name => HashMapStorage::new(),
+///     }
+/// }
+///
+/// # fn main() {
+/// let set = GameSet::new();
+/// # }
+/// ```
+#[macro_export]
+macro_rules! component_set {
+    ($(#[$attr: meta])* pub struct $name: ident { $($body: tt)* }) => {
+        component_set!(@build [$(#[$attr])*] [pub] $name;
+            [$crate::ecs::set::Empty] [$crate::ecs::set::Empty]; $($body)*);
+    };
+
+    ($(#[$attr: meta])* struct $name: ident { $($body: tt)* }) => {
+        component_set!(@build [$(#[$attr])*] [] $name;
+            [$crate::ecs::set::Empty] [$crate::ecs::set::Empty]; $($body)*);
+    };
+
+    // a component with custom storage.
+    (@build $attrs: tt $vis: tt $name: ident; [$($ty: tt)*] [$($ctor: tt)*];
+     $comp: ty => $storage: expr, $($rest: tt)*) => {
+        component_set!(@build $attrs $vis $name;
+            [$crate::ecs::set::SetEntry<$comp, $($ty)*>]
+            [$crate::ecs::set::Set::push_custom::<$comp>($($ctor)*, $storage)];
+            $($rest)*);
+    };
+
+    (@build $attrs: tt $vis: tt $name: ident; $ty: tt $ctor: tt; $comp: ty => $storage: expr) => {
+        component_set!(@build $attrs $vis $name; $ty $ctor; $comp => $storage,);
+    };
+
+    // a component with default storage.
+    (@build $attrs: tt $vis: tt $name: ident; [$($ty: tt)*] [$($ctor: tt)*];
+     $comp: ty, $($rest: tt)*) => {
+        component_set!(@build $attrs $vis $name;
+            [$crate::ecs::set::SetEntry<$comp, $($ty)*>]
+            [$crate::ecs::set::Set::push::<$comp>($($ctor)*)];
+            $($rest)*);
+    };
+
+    (@build $attrs: tt $vis: tt $name: ident; $ty: tt $ctor: tt; $comp: ty) => {
+        component_set!(@build $attrs $vis $name; $ty $ctor; $comp,);
+    };
+
+    (@build [$(#[$attr: meta])*] [$($vis: tt)*] $name: ident; [$($ty: tt)*] [$($ctor: tt)*];) => {
+        $(#[$attr])*
+        $($vis)* struct $name($($ty)*);
+
+        impl $name {
+            /// Create the set, with empty storage for every component.
+            pub fn new() -> Self {
+                $name($($ctor)*)
+            }
+        }
+
+        impl Default for $name {
+            fn default() -> Self {
+                $name::new()
+            }
+        }
+
+        impl $crate::ecs::set::Set for $name {
+            fn lock_storage<T: $crate::ecs::Component>(&self)
+            -> $crate::ecs::set::StorageGuard<T> {
+                self.0.lock_storage::<T>()
+            }
+
+            fn get_storage_mut<T: $crate::ecs::Component>(&mut self) -> &mut T::Storage {
+                self.0.get_storage_mut::<T>()
+            }
+
+            fn storage_version<T: $crate::ecs::Component>(&self) -> usize {
+                self.0.storage_version::<T>()
+            }
+
+            fn destroy(&mut self, e: $crate::ecs::Entity) {
+                self.0.destroy(e)
+            }
+
+            fn clone_entity(&mut self, from: $crate::ecs::VerifiedEntity, to: $crate::ecs::VerifiedEntity,
+                            map: &mut FnMut($crate::ecs::Entity) -> $crate::ecs::Entity) {
+                self.0.clone_entity(from, to, map)
+            }
+
+            fn transfer(&mut self, from: $crate::ecs::VerifiedEntity, into: &mut Self,
+                        to: $crate::ecs::VerifiedEntity,
+                        map: &mut FnMut($crate::ecs::Entity) -> $crate::ecs::Entity) {
+                self.0.transfer(from, &mut into.0, to, map)
+            }
+
+            fn snapshot(&self, out: &mut Vec<$crate::ecs::set::StorageSnapshot>) -> Result<(), &'static str> {
+                self.0.snapshot(out)
+            }
+
+            fn restore(&mut self, from: &[$crate::ecs::set::StorageSnapshot],
+                       entities: &$crate::ecs::EntityManager) {
+                self.0.restore(from, entities)
+            }
+
+            fn hash_state(&self, entities: &[$crate::ecs::Entity], state: &mut ::std::hash::Hasher) {
+                self.0.hash_state(entities, state)
+            }
+        }
+    };
+}
+
+/// Declare a named `World` type along with the component set it stores.
+///
+/// This takes the same component list as `component_set!`, and generates
+/// the set along with a `World` type alias.
+///
+/// # Examples
+/// ```
+/// #[macro_use] extern crate snorkium;
+/// use snorkium::ecs::{Component, DefaultStorage};
+///
+/// struct Position(f32, f32);
+/// impl Component for Position { type Storage = DefaultStorage<Self>; }
+///
+/// world! {
+///     pub GameWorld(GameSet) {
+///         Position,
+///     }
+/// }
+///
+/// # fn main() {
+/// let world = GameWorld::new(GameSet::new());
+/// # }
+/// ```
+#[macro_export]
+macro_rules! world {
+    ($(#[$attr: meta])* pub $world: ident($set: ident) { $($body: tt)* }) => {
+        component_set! { pub struct $set { $($body)* } }
+
+        $(#[$attr])*
+        pub type $world = $crate::ecs::World<$set>;
+    };
+
+    ($(#[$attr: meta])* $world: ident($set: ident) { $($body: tt)* }) => {
+        component_set! { struct $set { $($body)* } }
+
+        $(#[$attr])*
+        type $world = $crate::ecs::World<$set>;
+    };
+}
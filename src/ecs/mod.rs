@@ -1,37 +1,98 @@
 //! A multithreaded Entity Component System (ECS)
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hasher;
 use std::marker::PhantomData;
 use std::ops::Deref;
 
 use self::set::*;
 use self::query::*;
+use self::reflect::Reflect;
+use self::resources::{ResourceGuard, Resources};
 
 const ID_BITS: usize = 24;
 const MIN_UNUSED: usize = 1024;
 
+#[macro_use]
+mod macros;
+
+pub mod builder;
+pub mod determinism;
+pub mod hierarchy;
 pub mod query;
+pub mod reflect;
+pub mod relation;
+pub mod resources;
+pub mod schedule;
+pub mod serialize;
 pub mod set;
+pub mod snapshot;
+pub mod storage;
+pub mod transfer;
+pub mod transform;
 
 /// A component is a piece of raw data which is associated with an entity.
 ///
 /// "Systems" will typically iterate over all entities with a specific set of components,
 /// performing some action for each.
-pub trait Component: 'static + Copy + Send + Sync {
+///
+/// Components don't need to be `Copy`: strings, vectors, and boxed data are
+/// all fine. Storages move data out on removal and drop it on destruction.
+///
+/// Types are registered as components with an explicit implementation,
+/// or with `#[derive(Component)]` from the `snorkium_derive` crate:
+///
+/// ```
+/// use snorkium::ecs::{Component, DefaultStorage};
+///
+/// struct Velocity(f32, f32);
+///
+/// impl Component for Velocity {
+///     type Storage = DefaultStorage<Self>;
+/// }
+/// ```
+pub trait Component: 'static + Sized + Send + Sync {
     /// The data structure which stores component of this type.
     ///
-    /// By default, this will be the `DefaultStorage` structure,
+    /// This will usually be the `DefaultStorage` structure,
     /// which is good for almost all use-cases.
     /// However, for some components, it is more performant to store them
     /// in a special data structure with custom filters. A good example of this
     /// is positional data, which can be queried much more easily when stored
     /// in a quadtree or octree.
     type Storage: Storage<Self>;
+    
+    /// Make a copy of this component, for `World::clone_entity`.
+    ///
+    /// Components don't need to be `Clone`, so by default this returns `None`
+    /// and cloned entities won't have the component.
+    fn duplicate(&self) -> Option<Self> {
+        None
+    }
+    
+    /// Replace every entity handle within this component with the result of the function.
+    ///
+    /// This is used to fix up references when entities are cloned or moved
+    /// between worlds. By default the component is assumed to hold no handles.
+    fn map_entities(&mut self, _: &mut FnMut(Entity) -> Entity) {}
+    
+    /// Feed this component's state to a hasher, for `World::state_hash`, returning
+    /// whether it could be hashed.
+    ///
+    /// By default this returns `false`, and `World::state_hash` panics if any living
+    /// entity has the component. Components which shouldn't affect the hash can
+    /// return `true` without writing anything.
+    fn hash_state(&self, _: &mut Hasher) -> bool {
+        false
+    }
 }
 
-impl<T: 'static + Copy + Send + Sync> Component for T {
-    default type Storage = DefaultStorage<Self>;
-}
+/// A component which is saved and loaded along with the world.
+///
+/// Component data is written through its reflected `Value`, so entity handles
+/// within can be remapped on load. Components are saved once registered with a
+/// `serialize::Registry`. This can be derived with `#[component(serialize)]`.
+pub trait SerializeComponent: Component + Reflect {}
 
 /// Component data storage.
 ///
@@ -54,6 +115,7 @@ pub trait Storage<T: Component>: Sync + Send {
     fn remove(&mut self, e: VerifiedEntity) -> Option<T>;
     
     /// Destroy an entity's data without returning the data.
+    /// The data is dropped.
     ///
     /// This entity may not be alive.
     /// This will usually be called with entities that have been
@@ -63,6 +125,15 @@ pub trait Storage<T: Component>: Sync + Send {
     
     /// Return an iterator over all entities this stores data for.
     fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a>;
+    
+    /// Whether `entities` yields a fixed order, such as by key, rather than one
+    /// which depends on the history of allocations and removals.
+    ///
+    /// Queries in deterministic mode keep this order instead of sorting by id.
+    /// By default this is `false`.
+    fn is_ordered(&self) -> bool {
+        false
+    }
 }
 
 /// The default component data storage.
@@ -70,8 +141,8 @@ pub trait Storage<T: Component>: Sync + Send {
 /// Data is stored contiguously and can be iterated
 /// over very quickly.
 pub struct DefaultStorage<T: Component> {
-    // data vector -- this is tightly packed.
-    data: Vec<(Entity, T)>,
+    // data vector -- this is tightly packed. slots listed in `unused` are `None`.
+    data: Vec<(Entity, Option<T>)>,
     // loosely packed lookup table mapping entity ids to data indices.
     indices: Vec<Option<usize>>,
     // unused indices in the data table.
@@ -86,17 +157,31 @@ impl<T: Component> DefaultStorage<T> {
             unused: VecDeque::new(),
         }
     }
+    
+    // move an entity's data out of its slot, freeing the slot.
+    fn take(&mut self, e: Entity) -> Option<T> {
+        let id = e.id() as usize;
+        if let Some(&Some(idx)) = self.indices.get(id) {
+            if self.data[idx].0 == e {
+                self.indices[id] = None;
+                self.unused.push_back(idx);
+                return self.data[idx].1.take()
+            }
+        }
+        
+        None
+    }
 }
 
 impl<T: Component> Storage<T> for DefaultStorage<T> {    
     /// Sets the component for the given entity.
     fn set(&mut self, e: VerifiedEntity, data: T) {
         let id = e.entity().id() as usize;
-        while self.indices.len() < id as usize {
+        while self.indices.len() <= id {
             self.indices.push(None);
         }
         
-        let data = (e.entity(), data);
+        let data = (e.entity(), Some(data));
         
         if let Some(idx) = self.indices[id] {
             self.data[idx] = data;
@@ -105,7 +190,7 @@ impl<T: Component> Storage<T> for DefaultStorage<T> {
             self.indices[id] = Some(idx);
         } else {
             self.data.push(data);
-            self.indices[id] = Some(self.data.len());
+            self.indices[id] = Some(self.data.len() - 1);
         }
     }
     
@@ -122,7 +207,7 @@ impl<T: Component> Storage<T> for DefaultStorage<T> {
     fn get(&self, e: VerifiedEntity) -> Option<&T> {
         if let Some(&Some(idx)) = self.indices.get(e.entity().id() as usize) {
             if self.data[idx].0 == e.entity() {
-                return Some(&self.data[idx].1)
+                return self.data[idx].1.as_ref()
             }
         }
         
@@ -133,7 +218,7 @@ impl<T: Component> Storage<T> for DefaultStorage<T> {
     fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
         if let Some(&Some(idx)) = self.indices.get(e.entity().id() as usize) {
             if self.data[idx].0 == e.entity() {
-                return Some(&mut self.data[idx].1)
+                return self.data[idx].1.as_mut()
             }
         }
         
@@ -142,28 +227,15 @@ impl<T: Component> Storage<T> for DefaultStorage<T> {
     
     /// Remove an entity's data, returning it by value if it existed.
     fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
-        let id = e.entity().id() as usize;
-        if let Some(&Some(idx)) = self.indices.get(id) {
-            self.unused.push_back(idx);
-            self.indices[id] = None;
-            
-            if self.data[idx].0 == e.entity() {
-                return Some(self.data[idx].1)
-            }
-        }
-        
-        None
+        self.take(e.entity())
     }
     
     fn destroy(&mut self, e: Entity) {
-        if let Some(&Some(idx)) = self.indices.get(e.id() as usize) {
-            self.indices[e.id() as usize] = None;
-            self.unused.push_back(idx);
-        }
+        self.take(e);
     }
     
     fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
-        let iter = self.data.iter().map(|&(e, _)| e);
+        let iter = self.data.iter().filter(|(_, d)| d.is_some()).map(|&(e, _)| e);
         
         Box::new(iter)
     }
@@ -176,9 +248,15 @@ impl<T: Component> Default for DefaultStorage<T> {
 }
 
 /// Manages creation and deletion of entities.
+#[derive(Clone)]
 pub struct EntityManager {
     gens: Vec<u8>,
     unused: VecDeque<u32>,
+    // entities destroyed since storages were last cleaned up. only managers
+    // owned by a world remember them, since `World::maintain` drains them.
+    destroyed: Option<Vec<Entity>>,
+    // whether queries visit entities in order of id, for deterministic mode.
+    ordered: bool,
 }
 
 impl EntityManager {
@@ -187,6 +265,8 @@ impl EntityManager {
         EntityManager {
             gens: Vec::new(),
             unused: VecDeque::new(),
+            destroyed: None,
+            ordered: false,
         }    
     }
     
@@ -201,9 +281,21 @@ impl EntityManager {
         }
     }
     
+    /// All living entities, in order of id.
+    pub fn alive(&self) -> Vec<Entity> {
+        let unused: HashSet<u32> = self.unused.iter().cloned().collect();
+        (0..self.gens.len() as u32)
+            .filter(|id| !unused.contains(id))
+            .map(|id| Entity::new(self.gens[id as usize], id))
+            .collect()
+    }
+    
     /// Whether an entity is alive.
+    ///
+    /// Handles from after a restored snapshot may have ids
+    /// this doesn't know about, and are dead.
     pub fn is_alive(&self, entity: Entity) -> bool {
-        self.gens[entity.id() as usize] == entity.gen()
+        self.gens.get(entity.id() as usize).is_some_and(|&gen| gen == entity.gen())
     }
     
     /// Attempts to verify the entity given.
@@ -224,11 +316,23 @@ impl EntityManager {
     }  
      
     /// Destroys an entity. No-op if already dead.
+    ///
+    /// The entity's component data stays in its world's storages until
+    /// `World::maintain` is called, which `World::despawn` and the scheduler do.
     pub fn destroy(&mut self, entity: Entity) {
         if !self.is_alive(entity) { return; }
         
         self.gens[entity.id() as usize] += 1;
         self.unused.push_back(entity.id());
+        if let Some(ref mut destroyed) = self.destroyed { destroyed.push(entity) }
+    }
+    
+    /// Take the entities destroyed since the last call, so that
+    /// their component data can be cleaned up.
+    ///
+    /// This is always empty for managers which don't belong to a world.
+    pub fn drain_destroyed(&mut self) -> Vec<Entity> {
+        self.destroyed.as_mut().map(::std::mem::take).unwrap_or_default()
     }
 }
 
@@ -281,27 +385,89 @@ impl<'a> Deref for VerifiedEntity<'a> {
     }
 }
 
-/// The world stores component and entity data.
+/// The world stores component and entity data, along with resources.
 pub struct World<S: Set> {
     data: S,
     entities: EntityManager,
+    resources: Resources,
+}
+
+impl<S: Set> World<S> {
+    /// Create a new world with no entities or resources, storing components in the given set.
+    pub fn new(data: S) -> Self {
+        World {
+            data: data,
+            entities: EntityManager { destroyed: Some(Vec::new()), ..EntityManager::new() },
+            resources: Resources::new(),
+        }
+    }
+    
+    /// Get a handle to the world's data, for systems to process.
+    pub fn handle(&self) -> WorldHandle<S> {
+        WorldHandle {
+            data: &self.data,
+            entities: &self.entities,
+            resources: &self.resources,
+        }
+    }
+    
+    /// The world's resources.
+    pub fn resources(&self) -> &Resources {
+        &self.resources
+    }
+    
+    /// Mutable access to the world's resources, for adding and removing them.
+    pub fn resources_mut(&mut self) -> &mut Resources {
+        &mut self.resources
+    }
+    
+    /// Destroy an entity along with all of its components. No-op if already dead.
+    pub fn despawn(&mut self, e: Entity) {
+        if !self.entities.is_alive(e) { return }
+        
+        self.entities.destroy(e);
+        self.maintain();
+    }
+    
+    /// Clean up the component data of every entity destroyed
+    /// directly through the `EntityManager` since the last call.
+    ///
+    /// Until this is called, the destroyed entities are remembered
+    /// and their data is kept.
+    pub fn maintain(&mut self) {
+        for e in self.entities.drain_destroyed() {
+            self.data.destroy(e);
+        }
+    }
 }
 
 pub struct WorldHandle<'a, S: 'a + Set> {
     data: &'a S,
     entities: &'a EntityManager,
+    resources: &'a Resources,
+}
+
+impl<'a, S: 'a + Set> Clone for WorldHandle<'a, S> {
+    fn clone(&self) -> Self {
+        *self
+    }
 }
 
+impl<'a, S: 'a + Set> Copy for WorldHandle<'a, S> {}
+
 impl<'a, S: 'a + Set> WorldHandle<'a, S> {
     /// Create a query against the world data.
     ///
     /// # Examples
     /// ```
     /// use snorkium::ecs::*;
+    /// use snorkium::ecs::set::Set;
     /// #[derive(Clone, Copy)]
     /// struct Position(f32, f32);
+    /// impl Component for Position { type Storage = DefaultStorage<Self>; }
     /// #[derive(Clone, Copy)]
     /// struct Dot;
+    /// impl Component for Dot { type Storage = DefaultStorage<Self>; }
     ///     
     /// // imagine this draws a dot at the position.
     /// fn draw_dot(_: &Position) { }
@@ -320,6 +486,13 @@ impl<'a, S: 'a + Set> WorldHandle<'a, S> {
     where F: PipelineFactory {
         Query::new(&self.data, &self.entities, F::create())
     }
+    
+    /// Get exclusive access to a resource, if the world has one of the type.
+    ///
+    /// The resource stays locked until the guard is dropped.
+    pub fn resource<T: ::std::any::Any + Send>(&self) -> Option<ResourceGuard<'a, T>> {
+        self.resources.lock::<T>()
+    }
 }
 
 /// Systems are where the bulk of the work of the ECS is done.
@@ -355,5 +528,63 @@ mod tests {
         assert!(manager.is_alive(e1));
         assert!(!manager.is_alive(e2));
         assert!(!manager.is_alive(e3));
+
+        // nothing cleans up after a manager on its own, so it doesn't remember what it destroyed.
+        assert!(manager.drain_destroyed().is_empty());
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn non_copy_components() {
+        use std::sync::Arc;
+        
+        let mut manager = EntityManager::new();
+        struct Name(Arc<String>);
+        impl Component for Name {
+            type Storage = DefaultStorage<Self>;
+        }
+        
+        let mut storage = DefaultStorage::<Name>::new();
+        let name = Arc::new("snork".to_owned());
+        
+        let e1 = manager.next();
+        let e2 = manager.next();
+        storage.set(manager.verify(e1).unwrap(), Name(name.clone()));
+        storage.set(manager.verify(e2).unwrap(), Name(name.clone()));
+        assert_eq!(Arc::strong_count(&name), 3);
+        
+        let removed = storage.remove(manager.verify(e1).unwrap()).unwrap();
+        assert_eq!(*removed.0, "snork");
+        assert!(storage.remove(manager.verify(e1).unwrap()).is_none());
+        drop(removed);
+        
+        manager.destroy(e2);
+        storage.destroy(e2);
+        assert_eq!(Arc::strong_count(&name), 1);
+        assert_eq!(storage.entities().count(), 0);
+    }
+
+    #[test]
+    fn maintain_cleans_up_destroyed() {
+        struct Score(u32);
+        impl Component for Score {
+            type Storage = DefaultStorage<Self>;
+        }
+
+        component_set! {
+            struct TestSet { Score }
+        }
+
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().with(Score(1)).spawn();
+        let b = world.build_entity().with(Score(2)).spawn();
+
+        world.entities.destroy(a);
+        assert_eq!(world.data.lock_storage::<Score>().entities().count(), 2);
+
+        world.maintain();
+        assert!(world.entities.drain_destroyed().is_empty());
+        let remaining: Vec<_> = world.data.lock_storage::<Score>().entities().collect();
+        assert_eq!(remaining, vec![b]);
+        assert_eq!(world.data.lock_storage::<Score>().get(world.entities.verify(b).unwrap()).unwrap().0, 2);
+    }
+}
@@ -1,7 +1,7 @@
 use std::marker::PhantomData;
 
 use super::*;
-use super::set::{Set, LockedSubset};
+use super::set::{Set, LockedSubset, LockGroup};
 
 /// Filters are used to test properties of entities' data.
 ///
@@ -17,6 +17,17 @@ pub trait Filter {
     /// 
     /// This may only return true if the entity has the given component.
     fn pred(&self, &<Self::Component as Component>::Storage, VerifiedEntity) -> bool;
+    
+    /// The entities which might fulfill the predicate.
+    ///
+    /// By default, this is every entity in the storage. Filters over
+    /// specialized storages can override this to prune using the storage's index.
+    /// Every candidate will still be tested against `pred`. Candidates from a
+    /// storage which `is_ordered` should be yielded in the storage's order.
+    fn candidates<'a>(&'a self, storage: &'a <Self::Component as Component>::Storage)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        storage.entities()
+    }
 }
 
 /// A filter which tests whether an entity has a specific component.
@@ -45,7 +56,9 @@ pub trait Pipeline<'a>: Sized {
     /// fulfilling the pipeline's predicates to the functions along with
     /// relevant component data. This will output a vector of the returned
     /// outputs from the function.
-    fn for_each<F, U: Send, S: LockedSubset>(self, &S, &EntityManager, F) -> Vec<U>
+    ///
+    /// The storage for each filter's component is locked for the duration.
+    fn for_each<F, U: Send, S: Set>(self, &S, &EntityManager, F) -> Vec<U>
     where F: Sync + for <'b> Fn(VerifiedEntity, <Self as Pipeline<'b>>::Item) -> U;
 }
 
@@ -112,12 +125,15 @@ impl<'a, S: 'a + Set, P: 'a + Pipeline<'a>> Query<'a, S, P> {
     
     /// Perform an action for each entity which fits the properties of 
     /// the filter.
+    ///
+    /// Entities are visited in the order yielded by the first filter's
+    /// candidates, which is usually the order of its component's storage.
+    /// In deterministic mode they're visited in order of id instead, unless
+    /// that storage keeps a fixed order of its own.
     pub fn for_each<F, U: Send>(self, f: F) -> Vec<U>
     where F: Sync + for<'b> Fn(VerifiedEntity, <P as Pipeline<'b>>::Item) -> U {
-        // TODO: amend Pipeline so that we can get a LockGroup to pass to lock_subset.
         // TODO: have for_each return the locked subset along with the items.
-        let empty = ::ecs::set::Empty;
-        self.pipeline.for_each(&empty, self.entities, f)
+        self.pipeline.for_each(self.set, self.entities, f)
     }
 }
 
@@ -186,11 +202,18 @@ trait FilterExt: Filter {
 impl<F: Filter> FilterExt for F {
     fn all<'a>(&'a self, storage: &<Self::Component as Component>::Storage, em: &'a EntityManager)
     -> Vec<Option<VerifiedEntity>> {
-        storage.entities()
+        let mut entities: Vec<_> = self.candidates(storage)
             .filter_map(|e| em.verify(e))
             .filter(|e| self.pred(storage, *e))
             .map(Some)
-            .collect()
+            .collect();
+        
+        // storage order usually depends on allocation history, so it's replaced in deterministic mode.
+        if em.ordered && !storage.is_ordered() {
+            entities.sort_by_key(|e| e.as_ref().unwrap().id());
+        }
+        
+        entities
     }
     
     fn filter(&self, storage: &<Self::Component as Component>::Storage, 
@@ -209,8 +232,8 @@ macro_rules! pipeline_impl {
         impl<'a> Pipeline<'a> for () {
             type Item = ();
             
-            fn for_each<F, U: Send, S: LockedSubset>(self, _: &S, _: &EntityManager, _: F) -> Vec<U>
-            where F: 'a + Sync + for<'b> Fn(VerifiedEntity, <Self as Pipeline<'b>>::Item) -> U {
+            fn for_each<F, U: Send, S: Set>(self, _: &S, _: &EntityManager, _: F) -> Vec<U>
+            where F: Sync + for<'b> Fn(VerifiedEntity, <Self as Pipeline<'b>>::Item) -> U {
                 Vec::new()
             }
         }
@@ -222,10 +245,11 @@ macro_rules! pipeline_impl {
             type Item = (&'a <$f_id as Filter>::Component, $(&'a <$id as Filter>::Component,)*);
             
             #[allow(unused_mut)]
-            fn for_each<OP, U: Send, SET: LockedSubset>(self, set: &SET, entities: &EntityManager, f: OP) -> Vec<U>
-            where OP: 'a + Sync + for<'b> Fn(VerifiedEntity, <Self as Pipeline<'b>>::Item) -> U {  
-                // it's ok to unwrap the calls to get_storage() since this function is called with a subset
-                // that has been locked with this pipeline in mind.
+            fn for_each<OP, U: Send, SET: Set>(self, set: &SET, entities: &EntityManager, f: OP) -> Vec<U>
+            where OP: Sync + for<'b> Fn(VerifiedEntity, <Self as Pipeline<'b>>::Item) -> U {  
+                // it's ok to unwrap the calls to get_storage() since the subset
+                // is locked with this pipeline's components.
+                let set = <($f_id::Component, $($id::Component,)*) as LockGroup>::lock(set);
                               
                 // the first filter is special-cased -- we use the "all" method of FilterExt here
                 // to get a vector which will get whittled down.
@@ -0,0 +1,328 @@
+//! Runtime reflection of component data.
+//!
+//! Reflected types can be converted to and from a format-independent `Value` tree,
+//! which tools and data formats can inspect without knowing the concrete type.
+//! For components, this is usually derived with `#[component(reflect)]`.
+
+use std::error::Error;
+use std::fmt;
+
+use super::Entity;
+
+/// A dynamically-typed value.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Unit,
+    Bool(bool),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    Entity(Entity),
+    Option(Option<Box<Value>>),
+    List(Vec<Value>),
+    Tuple(Vec<Value>),
+    Struct(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// A short name for the kind of this value, for error messages.
+    pub fn kind(&self) -> &'static str {
+        match *self {
+            Value::Unit => "unit",
+            Value::Bool(_) => "bool",
+            Value::Int(_) | Value::UInt(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Str(_) => "string",
+            Value::Entity(_) => "entity",
+            Value::Option(_) => "option",
+            Value::List(_) => "list",
+            Value::Tuple(_) => "tuple",
+            Value::Struct(_) => "struct",
+        }
+    }
+
+    /// Call the function with every entity handle within this value.
+    pub fn map_entities<F: FnMut(Entity) -> Entity>(&mut self, f: &mut F) {
+        match *self {
+            Value::Entity(ref mut e) => *e = f(*e),
+            Value::Option(Some(ref mut v)) => v.map_entities(f),
+            Value::List(ref mut vs) | Value::Tuple(ref mut vs) => {
+                for v in vs { v.map_entities(f) }
+            }
+            Value::Struct(ref mut fields) => {
+                for &mut (_, ref mut v) in fields { v.map_entities(f) }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// An error converting a `Value` into a concrete type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReflectError {
+    /// The value was of the wrong kind.
+    Mismatch { expected: &'static str, found: &'static str },
+    /// A number didn't fit into the target type.
+    OutOfRange(&'static str),
+    /// A struct was missing a field.
+    MissingField(&'static str),
+    /// A struct had a field which the type doesn't.
+    UnknownField(String),
+    /// A tuple had the wrong number of elements.
+    Length { expected: usize, found: usize },
+}
+
+impl fmt::Display for ReflectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReflectError::Mismatch { expected, found } =>
+                write!(f, "expected {}, found {}", expected, found),
+            ReflectError::OutOfRange(ty) => write!(f, "number out of range for {}", ty),
+            ReflectError::MissingField(name) => write!(f, "missing field `{}`", name),
+            ReflectError::UnknownField(ref name) => write!(f, "unknown field `{}`", name),
+            ReflectError::Length { expected, found } =>
+                write!(f, "expected {} elements, found {}", expected, found),
+        }
+    }
+}
+
+impl Error for ReflectError {
+    fn description(&self) -> &str {
+        "value does not match the reflected type"
+    }
+}
+
+/// A type which can be converted to and from a `Value`.
+pub trait Reflect: Sized {
+    /// The name of this type.
+    fn type_name() -> &'static str;
+
+    /// Convert this into a value.
+    fn to_value(&self) -> Value;
+
+    /// Convert a value back into this type.
+    fn from_value(value: Value) -> Result<Self, ReflectError>;
+}
+
+/// The fields of a `Value::Struct`, for use in `Reflect::from_value` implementations.
+pub struct Fields {
+    fields: Vec<(String, Value)>,
+}
+
+impl Fields {
+    /// Unpack a struct value.
+    pub fn new(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::Struct(fields) => Ok(Fields { fields: fields }),
+            other => Err(ReflectError::Mismatch { expected: "struct", found: other.kind() }),
+        }
+    }
+
+    /// Take a field out and convert it.
+    pub fn take<T: Reflect>(&mut self, name: &'static str) -> Result<T, ReflectError> {
+        match self.fields.iter().position(|(n, _)| n == name) {
+            Some(idx) => T::from_value(self.fields.swap_remove(idx).1),
+            None => Err(ReflectError::MissingField(name)),
+        }
+    }
+
+    /// Fail if any fields remain which haven't been taken.
+    pub fn finish(self) -> Result<(), ReflectError> {
+        match self.fields.into_iter().next() {
+            Some((name, _)) => Err(ReflectError::UnknownField(name)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Unpack a tuple value with the given number of elements,
+/// for use in `Reflect::from_value` implementations.
+pub fn tuple_elements(value: Value, len: usize) -> Result<::std::vec::IntoIter<Value>, ReflectError> {
+    match value {
+        Value::Tuple(ref vs) if vs.len() != len =>
+            Err(ReflectError::Length { expected: len, found: vs.len() }),
+        Value::Tuple(vs) => Ok(vs.into_iter()),
+        other => Err(ReflectError::Mismatch { expected: "tuple", found: other.kind() }),
+    }
+}
+
+macro_rules! reflect_int {
+    ($($t: ident)*) => {
+        $(
+        impl Reflect for $t {
+            fn type_name() -> &'static str { stringify!($t) }
+
+            #[allow(unused_comparisons)]
+            fn to_value(&self) -> Value {
+                if *self < 0 { Value::Int(*self as i64) } else { Value::UInt(*self as u64) }
+            }
+
+            fn from_value(value: Value) -> Result<Self, ReflectError> {
+                let out_of_range = ReflectError::OutOfRange(stringify!($t));
+                match value {
+                    Value::Int(i) => {
+                        if i < $t::MIN as i64 || (i > 0 && i as u64 > $t::MAX as u64) {
+                            return Err(out_of_range)
+                        }
+                        Ok(i as $t)
+                    }
+                    Value::UInt(u) => {
+                        if u > $t::MAX as u64 { return Err(out_of_range) }
+                        Ok(u as $t)
+                    }
+                    other => Err(ReflectError::Mismatch { expected: "integer", found: other.kind() }),
+                }
+            }
+        }
+        )*
+    }
+}
+
+reflect_int!(i8 i16 i32 i64 isize u8 u16 u32 u64 usize);
+
+macro_rules! reflect_float {
+    ($($t: ident)*) => {
+        $(
+        impl Reflect for $t {
+            fn type_name() -> &'static str { stringify!($t) }
+
+            fn to_value(&self) -> Value { Value::Float(*self as f64) }
+
+            fn from_value(value: Value) -> Result<Self, ReflectError> {
+                match value {
+                    Value::Float(f) => Ok(f as $t),
+                    Value::Int(i) => Ok(i as $t),
+                    Value::UInt(u) => Ok(u as $t),
+                    other => Err(ReflectError::Mismatch { expected: "float", found: other.kind() }),
+                }
+            }
+        }
+        )*
+    }
+}
+
+reflect_float!(f32 f64);
+
+impl Reflect for bool {
+    fn type_name() -> &'static str { "bool" }
+
+    fn to_value(&self) -> Value { Value::Bool(*self) }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other => Err(ReflectError::Mismatch { expected: "bool", found: other.kind() }),
+        }
+    }
+}
+
+impl Reflect for String {
+    fn type_name() -> &'static str { "String" }
+
+    fn to_value(&self) -> Value { Value::Str(self.clone()) }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::Str(s) => Ok(s),
+            other => Err(ReflectError::Mismatch { expected: "string", found: other.kind() }),
+        }
+    }
+}
+
+impl Reflect for Entity {
+    fn type_name() -> &'static str { "Entity" }
+
+    fn to_value(&self) -> Value { Value::Entity(*self) }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::Entity(e) => Ok(e),
+            other => Err(ReflectError::Mismatch { expected: "entity", found: other.kind() }),
+        }
+    }
+}
+
+impl<T: Reflect> Reflect for Option<T> {
+    fn type_name() -> &'static str { "Option" }
+
+    fn to_value(&self) -> Value {
+        Value::Option(self.as_ref().map(|v| Box::new(v.to_value())))
+    }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::Option(None) => Ok(None),
+            Value::Option(Some(v)) => T::from_value(*v).map(Some),
+            other => Err(ReflectError::Mismatch { expected: "option", found: other.kind() }),
+        }
+    }
+}
+
+impl<T: Reflect> Reflect for Vec<T> {
+    fn type_name() -> &'static str { "Vec" }
+
+    fn to_value(&self) -> Value {
+        Value::List(self.iter().map(Reflect::to_value).collect())
+    }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        match value {
+            Value::List(vs) => vs.into_iter().map(T::from_value).collect(),
+            other => Err(ReflectError::Mismatch { expected: "list", found: other.kind() }),
+        }
+    }
+}
+
+impl<T: Reflect> Reflect for Box<T> {
+    fn type_name() -> &'static str { T::type_name() }
+
+    fn to_value(&self) -> Value { (**self).to_value() }
+
+    fn from_value(value: Value) -> Result<Self, ReflectError> {
+        T::from_value(value).map(Box::new)
+    }
+}
+
+macro_rules! reflect_tuple {
+    ($len: expr; $($id: ident $num: tt)*) => {
+        impl<$($id: Reflect,)*> Reflect for ($($id,)*) {
+            fn type_name() -> &'static str { "tuple" }
+
+            fn to_value(&self) -> Value {
+                Value::Tuple(vec![$(self.$num.to_value(),)*])
+            }
+
+            fn from_value(value: Value) -> Result<Self, ReflectError> {
+                let mut elements = tuple_elements(value, $len)?;
+                Ok(($($id::from_value(elements.next().unwrap())?,)*))
+            }
+        }
+    }
+}
+
+reflect_tuple!(2; A 0 B 1);
+reflect_tuple!(3; A 0 B 1 C 2);
+reflect_tuple!(4; A 0 B 1 C 2 D 3);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let v = vec![(1u8, -2.5f32), (200, 0.0)];
+        assert_eq!(Vec::<(u8, f32)>::from_value(v.to_value()).unwrap(), v);
+
+        assert_eq!(u8::from_value(Value::UInt(256)), Err(ReflectError::OutOfRange("u8")));
+        assert_eq!(i8::from_value(Value::Int(-128)), Ok(-128));
+        assert!(String::from_value(Value::Bool(true)).is_err());
+
+        let mut fields = Fields::new(Value::Struct(vec![
+            ("name".to_owned(), "snork".to_owned().to_value()),
+            ("extra".to_owned(), Value::Unit),
+        ])).unwrap();
+        assert_eq!(fields.take::<String>("name").unwrap(), "snork");
+        assert_eq!(fields.finish(), Err(ReflectError::UnknownField("extra".to_owned())));
+    }
+}
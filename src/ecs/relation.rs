@@ -0,0 +1,503 @@
+//! Typed, many-to-many relations between entities.
+//!
+//! A relation type `R` links a source entity to any number of targets, with
+//! a value of `R` on each link. Links are stored as the `Related<R>` component of
+//! the source, in a `RelationStorage` which also indexes them by target, so they
+//! can be followed in both directions.
+//!
+//! When either side of a link is destroyed, the link is removed along with the
+//! rest of the entity's data by `World::despawn` or `World::maintain`. Until then,
+//! links to dead entities are skipped by queries.
+//!
+//! # Examples
+//! ```
+//! #[macro_use] extern crate snorkium;
+//! use snorkium::ecs::relation::{Relation, Related};
+//!
+//! struct Likes(u32);
+//! impl Relation for Likes {}
+//!
+//! world! {
+//!     pub GameWorld(GameSet) { Related<Likes> }
+//! }
+//!
+//! # fn main() {
+//! let mut world = GameWorld::new(GameSet::new());
+//! let (a, b) = (world.build_entity().spawn(), world.build_entity().spawn());
+//!
+//! world.relate(a, b, Likes(10)).unwrap();
+//! assert_eq!(world.targets::<Likes>(a), vec![b]);
+//! assert_eq!(world.sources::<Likes>(b), vec![a]);
+//! # }
+//! ```
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hasher;
+use std::marker::PhantomData;
+
+use super::*;
+use super::query::Filter;
+use super::set::Set;
+
+/// A kind of link between entities, with the data stored on each link.
+pub trait Relation: 'static + Send + Sync {}
+
+/// The links of one relation type from a source entity, in the order they were made.
+///
+/// Links changed through the storage's `get_mut` are indexed by target again
+/// on the next mutable access to the storage; until then they're found by
+/// checking every link handed out that way.
+///
+/// Links aren't copied when an entity is cloned, since the link data
+/// needn't be `Clone`, but they do move with an entity to another world.
+pub struct Related<R: Relation> {
+    links: Vec<(Entity, R)>,
+}
+
+impl<R: Relation> Related<R> {
+    /// No links.
+    pub fn new() -> Self {
+        Related { links: Vec::new() }
+    }
+
+    /// Add a link, replacing any existing link to the same target.
+    pub fn with(mut self, target: Entity, data: R) -> Self {
+        self.insert(target, data);
+        self
+    }
+
+    /// The data on the link to a target.
+    pub fn get(&self, target: Entity) -> Option<&R> {
+        self.links.iter().find(|&&(e, _)| e == target).map(|(_, data)| data)
+    }
+
+    /// Mutable access to the data on the link to a target.
+    pub fn get_mut(&mut self, target: Entity) -> Option<&mut R> {
+        self.links.iter_mut().find(|&&mut (e, _)| e == target).map(|&mut (_, ref mut data)| data)
+    }
+
+    /// Every target along with the data on its link.
+    ///
+    /// This includes targets which have died but haven't been cleaned up yet.
+    pub fn iter<'a>(&'a self) -> Box<Iterator<Item=(Entity, &'a R)> + 'a> {
+        Box::new(self.links.iter().map(|&(e, ref data)| (e, data)))
+    }
+
+    /// The number of links.
+    pub fn len(&self) -> usize {
+        self.links.len()
+    }
+
+    /// Whether there are no links.
+    pub fn is_empty(&self) -> bool {
+        self.links.is_empty()
+    }
+
+    /// Add a link, returning the data on the link to the same target it replaces.
+    pub fn insert(&mut self, target: Entity, data: R) -> Option<R> {
+        match self.links.iter().position(|&(e, _)| e == target) {
+            Some(idx) => Some(::std::mem::replace(&mut self.links[idx].1, data)),
+            None => {
+                self.links.push((target, data));
+                None
+            }
+        }
+    }
+
+    /// Remove the link to a target, returning its data.
+    pub fn remove(&mut self, target: Entity) -> Option<R> {
+        self.links.iter().position(|&(e, _)| e == target).map(|idx| self.links.remove(idx).1)
+    }
+}
+
+impl<R: Relation> Default for Related<R> {
+    fn default() -> Self {
+        Related::new()
+    }
+}
+
+impl<R: Relation> Component for Related<R> {
+    type Storage = RelationStorage<R>;
+
+    fn map_entities(&mut self, map: &mut FnMut(Entity) -> Entity) {
+        // targets mapped to the same entity keep the first link.
+        for (target, data) in ::std::mem::take(&mut self.links) {
+            let target = map(target);
+            if self.get(target).is_none() { self.links.push((target, data)); }
+        }
+    }
+
+    // only the targets are hashed, since the link data needn't be hashable.
+    fn hash_state(&self, state: &mut Hasher) -> bool {
+        state.write_usize(self.links.len());
+        for &(e, _) in &self.links {
+            state.write_u32(e.id());
+            state.write_u8(e.gen());
+        }
+        true
+    }
+}
+
+/// Storage for the links of a relation, indexed by both source and target.
+pub struct RelationStorage<R: Relation> {
+    outgoing: HashMap<Entity, Related<R>>,
+    // sources linking to each target.
+    incoming: HashMap<Entity, Vec<Entity>>,
+    // sources handed out mutably, left out of the index until the next mutable access.
+    pending: Vec<Entity>,
+}
+
+impl<R: Relation> RelationStorage<R> {
+    /// Create a new, empty storage.
+    pub fn new() -> Self {
+        RelationStorage {
+            outgoing: HashMap::new(),
+            incoming: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Link a source to a target, returning the data on the
+    /// link it replaces if there was one.
+    pub fn relate(&mut self, source: VerifiedEntity, target: VerifiedEntity, data: R) -> Option<R> {
+        self.reindex();
+
+        let (source, target) = (source.entity(), target.entity());
+        let old = self.outgoing.entry(source).or_default().insert(target, data);
+
+        if old.is_none() {
+            self.incoming.entry(target).or_default().push(source);
+        }
+
+        old
+    }
+
+    /// Remove the link from a source to a target, returning its data.
+    ///
+    /// Either entity may be dead.
+    pub fn unrelate(&mut self, source: Entity, target: Entity) -> Option<R> {
+        self.reindex();
+
+        let (data, empty) = match self.outgoing.get_mut(&source) {
+            Some(related) => (related.remove(target), related.is_empty()),
+            None => return None,
+        };
+
+        if empty { self.outgoing.remove(&source); }
+        if data.is_some() { self.unindex(source, target); }
+        data
+    }
+
+    /// The data on the link from a source to a target.
+    pub fn relation(&self, source: Entity, target: Entity) -> Option<&R> {
+        self.outgoing.get(&source).and_then(|related| related.get(target))
+    }
+
+    /// Mutable access to the data on the link from a source to a target.
+    pub fn relation_mut(&mut self, source: Entity, target: Entity) -> Option<&mut R> {
+        self.outgoing.get_mut(&source).and_then(|related| related.get_mut(target))
+    }
+
+    /// The living targets of a source, along with the data on each link.
+    pub fn targets<'a>(&'a self, source: VerifiedEntity, entities: &'a EntityManager)
+    -> Box<Iterator<Item=(Entity, &'a R)> + 'a> {
+        match self.outgoing.get(&source.entity()) {
+            Some(related) => Box::new(related.iter().filter(move |&(e, _)| entities.is_alive(e))),
+            None => Box::new(None.into_iter()),
+        }
+    }
+
+    /// The living sources linking to a target, along with the data on each link.
+    pub fn sources<'a>(&'a self, target: VerifiedEntity, entities: &'a EntityManager)
+    -> Box<Iterator<Item=(Entity, &'a R)> + 'a> {
+        let target = target.entity();
+        Box::new(self.linking_to(target).filter(move |&e| entities.is_alive(e)).map(move |e| {
+            (e, self.relation(e, target).unwrap())
+        }))
+    }
+
+    // every source linking to a target, whether indexed or pending.
+    fn linking_to<'a>(&'a self, target: Entity) -> Box<Iterator<Item=Entity> + 'a> {
+        let indexed = self.incoming.get(&target).into_iter().flat_map(|sources| sources.iter());
+        let pending = self.pending.iter().filter(move |&&e| self.relation(e, target).is_some());
+        Box::new(indexed.chain(pending).cloned())
+    }
+
+    fn reindex(&mut self) {
+        for source in ::std::mem::take(&mut self.pending) {
+            if let Some(related) = self.outgoing.get(&source) {
+                for &(target, _) in &related.links {
+                    self.incoming.entry(target).or_default().push(source);
+                }
+            }
+        }
+    }
+
+    // remove the source from the target's index entry.
+    fn unindex(&mut self, source: Entity, target: Entity) {
+        let empty = match self.incoming.get_mut(&target) {
+            Some(sources) => {
+                sources.retain(|&e| e != source);
+                sources.is_empty()
+            }
+            None => false,
+        };
+
+        if empty { self.incoming.remove(&target); }
+    }
+
+    // remove all links from a source.
+    fn take(&mut self, source: Entity) -> Option<Related<R>> {
+        let related = self.outgoing.remove(&source);
+        if let Some(ref related) = related {
+            for &(target, _) in &related.links {
+                self.unindex(source, target);
+            }
+        }
+
+        related
+    }
+}
+
+impl<R: Relation> Default for RelationStorage<R> {
+    fn default() -> Self {
+        RelationStorage::new()
+    }
+}
+
+impl<R: Relation> Storage<Related<R>> for RelationStorage<R> {
+    fn set(&mut self, e: VerifiedEntity, data: Related<R>) {
+        self.reindex();
+
+        let source = e.entity();
+        self.take(source);
+
+        for &(target, _) in &data.links {
+            self.incoming.entry(target).or_default().push(source);
+        }
+
+        self.outgoing.insert(source, data);
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.outgoing.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&Related<R>> {
+        self.outgoing.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut Related<R>> {
+        self.reindex();
+
+        let source = e.entity();
+        let targets: Vec<Entity> = match self.outgoing.get(&source) {
+            Some(related) => related.links.iter().map(|&(target, _)| target).collect(),
+            None => return None,
+        };
+
+        // the caller may change the links, so the source is indexed again later.
+        for target in targets {
+            self.unindex(source, target);
+        }
+
+        self.pending.push(source);
+        self.outgoing.get_mut(&source)
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<Related<R>> {
+        self.reindex();
+        self.take(e.entity())
+    }
+
+    /// Removes the links from the entity, and the links to it.
+    fn destroy(&mut self, e: Entity) {
+        self.reindex();
+        self.take(e);
+
+        for source in self.incoming.remove(&e).unwrap_or_default() {
+            let empty = match self.outgoing.get_mut(&source) {
+                Some(related) => {
+                    related.remove(e);
+                    related.is_empty()
+                }
+                None => false,
+            };
+
+            if empty { self.outgoing.remove(&source); }
+        }
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.outgoing.keys().cloned())
+    }
+}
+
+/// A filter for the sources of links to a target.
+///
+/// Only the target's sources are visited, through the storage's index.
+pub struct RelatedTo<R> {
+    target: Entity,
+    _marker: PhantomData<R>,
+}
+
+impl<R> RelatedTo<R> {
+    /// Create a new filter for sources linking to the given target.
+    pub fn new(target: Entity) -> Self {
+        RelatedTo {
+            target: target,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<R: Relation> Filter for RelatedTo<R> {
+    type Component = Related<R>;
+
+    fn pred(&self, storage: &RelationStorage<R>, e: VerifiedEntity) -> bool {
+        storage.relation(e.entity(), self.target).is_some()
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a RelationStorage<R>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        storage.linking_to(self.target)
+    }
+}
+
+/// An error when linking a dead entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeadEntity(pub Entity);
+
+impl fmt::Display for DeadEntity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "entity {:?} is dead", self.0)
+    }
+}
+
+impl Error for DeadEntity {
+    fn description(&self) -> &str {
+        "dead entity"
+    }
+}
+
+impl<S: Set> World<S> {
+    /// Link a source to a target, returning the data on the link it
+    /// replaces if there was one.
+    pub fn relate<R: Relation>(&mut self, source: Entity, target: Entity, data: R)
+    -> Result<Option<R>, DeadEntity> {
+        let source = self.entities.verify(source).ok_or(DeadEntity(source))?;
+        let target = self.entities.verify(target).ok_or(DeadEntity(target))?;
+
+        Ok(self.data.get_storage_mut::<Related<R>>().relate(source, target, data))
+    }
+
+    /// Remove the link from a source to a target, returning its data.
+    pub fn unrelate<R: Relation>(&mut self, source: Entity, target: Entity) -> Option<R> {
+        self.data.get_storage_mut::<Related<R>>().unrelate(source, target)
+    }
+
+    /// The living targets of a source, in the order they were linked.
+    pub fn targets<R: Relation>(&self, source: Entity) -> Vec<Entity> {
+        match self.entities.verify(source) {
+            Some(source) => {
+                let storage = self.data.lock_storage::<Related<R>>();
+                let targets = storage.targets(source, &self.entities).map(|(e, _)| e).collect();
+                targets
+            }
+            None => Vec::new(),
+        }
+    }
+
+    /// The living sources linking to a target.
+    pub fn sources<R: Relation>(&self, target: Entity) -> Vec<Entity> {
+        match self.entities.verify(target) {
+            Some(target) => {
+                let storage = self.data.lock_storage::<Related<R>>();
+                let sources = storage.sources(target, &self.entities).map(|(e, _)| e).collect();
+                sources
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Likes(u32);
+    impl Relation for Likes {}
+
+    struct Owns;
+    impl Relation for Owns {}
+
+    component_set! {
+        struct TestSet { Related<Likes>, Related<Owns> }
+    }
+
+    #[test]
+    fn relations() {
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().spawn();
+        let b = world.build_entity().spawn();
+        let c = world.build_entity().with(Related::new().with(b, Likes(1)).with(a, Likes(2))).spawn();
+
+        world.relate(a, b, Likes(3)).unwrap();
+        world.relate(b, c, Owns).unwrap();
+        assert_eq!(world.targets::<Likes>(c), vec![b, a]);
+        assert_eq!(world.targets::<Owns>(b), vec![c]);
+        assert!(world.targets::<Owns>(a).is_empty());
+
+        // relation-aware query: everything which likes b, with how much.
+        let mut likes_b = world.handle().query::<()>()
+            .with_filtered(RelatedTo::<Likes>::new(b))
+            .for_each(|e, (related,)| (e.entity(), related.get(b).unwrap().0));
+        likes_b.sort_by_key(|&(_, n)| n);
+        assert_eq!(likes_b, vec![(c, 1), (a, 3)]);
+
+        assert_eq!(world.unrelate::<Likes>(c, a).map(|l| l.0), Some(2));
+        assert!(world.sources::<Likes>(a).is_empty());
+
+        // destroyed entities are skipped right away, and cleaned up on maintain.
+        world.entities.destroy(b);
+        assert_eq!(world.sources::<Likes>(b), vec![]);
+        assert!(world.targets::<Likes>(a).is_empty());
+
+        world.maintain();
+        let storage = world.data.lock_storage::<Related<Likes>>();
+        assert_eq!(storage.entities().count(), 0);
+        assert!(storage.incoming.is_empty());
+        assert!(world.data.lock_storage::<Related<Owns>>().entities().next().is_none());
+    }
+
+    #[test]
+    fn links_changed_in_place() {
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().spawn();
+        let b = world.build_entity().spawn();
+        let c = world.build_entity().with(Related::new().with(a, Likes(1))).spawn();
+
+        {
+            let v = world.entities.verify(c).unwrap();
+            let related = world.data.get_storage_mut::<Related<Likes>>().get_mut(v).unwrap();
+            related.get_mut(a).unwrap().0 = 5;
+            related.remove(a);
+            related.insert(b, Likes(2));
+        }
+
+        // found while pending, before the storage is indexed again.
+        assert!(world.sources::<Likes>(a).is_empty());
+        let likes_b = world.handle().query::<()>()
+            .with_filtered(RelatedTo::<Likes>::new(b))
+            .for_each(|e, (related,)| (e.entity(), related.get(b).unwrap().0));
+        assert_eq!(likes_b, vec![(c, 2)]);
+
+        world.relate(a, b, Likes(3)).unwrap();
+        let mut sources = world.sources::<Likes>(b);
+        sources.sort_by_key(|e| e.id());
+        assert_eq!(sources, vec![a, c]);
+        assert_eq!(world.data.lock_storage::<Related<Likes>>().incoming[&b].len(), 2);
+    }
+}
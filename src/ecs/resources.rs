@@ -0,0 +1,136 @@
+//! Global data stored alongside the entities of a world.
+//!
+//! A resource is a single value of its type, such as the current time or the
+//! input state, which isn't attached to any entity. Systems reach resources
+//! through their `WorldHandle`, which locks each one while it's in use.
+//!
+//! Resources are only part of `World::state_hash` when inserted with
+//! `insert_hashed`.
+
+use std::any::{self, Any, TypeId};
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard};
+
+// hashes a resource, given as `Any`, into the state hash.
+type HashFn = fn(&Any, &mut Hasher);
+
+/// A map holding at most one value of each type.
+#[derive(Default)]
+pub struct Resources {
+    map: HashMap<TypeId, Mutex<Box<Any + Send>>>,
+    // resources included in the state hash, with their type names to order them by.
+    hashed: HashMap<TypeId, (&'static str, HashFn)>,
+}
+
+impl Resources {
+    /// No resources.
+    pub fn new() -> Self {
+        Resources::default()
+    }
+
+    /// Insert a resource, returning the one of the same type it replaces.
+    pub fn insert<T: Any + Send>(&mut self, value: T) -> Option<T> {
+        self.hashed.remove(&TypeId::of::<T>());
+        self.map.insert(TypeId::of::<T>(), Mutex::new(Box::new(value))).map(unbox)
+    }
+
+    /// Insert a resource which is part of `World::state_hash`, returning
+    /// the one of the same type it replaces.
+    pub fn insert_hashed<T: Any + Send + Hash>(&mut self, value: T) -> Option<T> {
+        let old = self.insert(value);
+        self.hashed.insert(TypeId::of::<T>(), (any::type_name::<T>(), hash_resource::<T>));
+        old
+    }
+
+    /// Remove a resource.
+    pub fn remove<T: Any + Send>(&mut self) -> Option<T> {
+        self.hashed.remove(&TypeId::of::<T>());
+        self.map.remove(&TypeId::of::<T>()).map(unbox)
+    }
+
+    /// Whether there is a resource of the type.
+    pub fn contains<T: Any + Send>(&self) -> bool {
+        self.map.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Get exclusive access to a resource by locking it.
+    pub fn lock<T: Any + Send>(&self) -> Option<ResourceGuard<T>> {
+        self.map.get(&TypeId::of::<T>()).map(|resource| ResourceGuard {
+            guard: resource.lock().unwrap(),
+            _marker: PhantomData,
+        })
+    }
+
+    /// Get a mutable reference to a resource.
+    pub fn get_mut<T: Any + Send>(&mut self) -> Option<&mut T> {
+        self.map.get_mut(&TypeId::of::<T>()).map(|resource| {
+            resource.get_mut().unwrap().downcast_mut::<T>().unwrap()
+        })
+    }
+
+    /// Feed the resources inserted with `insert_hashed` to a hasher,
+    /// in order of their type names.
+    pub fn hash_state(&self, state: &mut Hasher) {
+        let mut hashed: Vec<_> = self.hashed.iter().collect();
+        hashed.sort_by_key(|&(_, &(name, _))| name);
+
+        state.write_usize(hashed.len());
+        for (id, &(name, hash)) in hashed {
+            state.write(name.as_bytes());
+            let resource = self.map[id].lock().unwrap();
+            hash(&**resource, state);
+        }
+    }
+}
+
+fn hash_resource<T: Any + Hash>(resource: &Any, state: &mut Hasher) {
+    resource.downcast_ref::<T>().unwrap().hash(&mut &mut *state)
+}
+
+fn unbox<T: Any>(resource: Mutex<Box<Any + Send>>) -> T {
+    *resource.into_inner().unwrap().downcast::<T>().ok().unwrap()
+}
+
+/// A locked resource.
+pub struct ResourceGuard<'a, T: 'a> {
+    guard: MutexGuard<'a, Box<Any + Send>>,
+    _marker: PhantomData<&'a mut T>,
+}
+
+impl<'a, T: Any> Deref for ResourceGuard<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard.downcast_ref::<T>().unwrap()
+    }
+}
+
+impl<'a, T: Any> DerefMut for ResourceGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard.downcast_mut::<T>().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert_and_lock() {
+        let mut resources = Resources::new();
+        assert_eq!(resources.insert(3u32), None);
+        assert_eq!(resources.insert(4u32), Some(3));
+        resources.insert("name".to_owned());
+
+        *resources.lock::<u32>().unwrap() += 1;
+        assert_eq!(*resources.get_mut::<u32>().unwrap(), 5);
+        assert_eq!(&*resources.lock::<String>().unwrap(), "name");
+
+        assert!(resources.lock::<i64>().is_none());
+        assert_eq!(resources.remove::<u32>(), Some(5));
+        assert!(!resources.contains::<u32>());
+    }
+}
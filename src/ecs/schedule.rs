@@ -0,0 +1,532 @@
+//! Running a list of systems against a world.
+//!
+//! Systems are grouped into stages, which run one after another in the order
+//! they were added, with destroyed entities cleaned up after each. Within a stage,
+//! systems can be constrained to run before or after others. The scheduler
+//! resolves these constraints into a `Plan` of batches, where each batch only
+//! depends on those before it.
+//!
+//! The systems of a batch run in parallel unless the world is in deterministic
+//! mode, in which case everything runs one at a time in the order of the plan,
+//! breaking ties by the order systems were added. Systems running in parallel
+//! shouldn't hold a storage locked while locking another, or they may deadlock.
+//!
+//! Whether a system runs can also depend on the world's resources, through
+//! conditions checked just before its batch, and systems can be disabled by name.
+//! A system which doesn't run still counts as done for the ones after it.
+
+use std::error::Error;
+use std::fmt;
+
+use super::*;
+use super::resources::Resources;
+use super::set::Set;
+
+/// The stage systems are added to if no stage has been added first.
+pub const DEFAULT_STAGE: &str = "main";
+
+// a system, boxed so that systems of different types can be stored together.
+type Run<S> = Box<FnMut(WorldHandle<S>) + Send>;
+
+// whether a system should run, given the world's resources.
+type Condition = Box<FnMut(&Resources) -> bool + Send>;
+
+struct Entry<S: Set> {
+    name: String,
+    stage: usize,
+    run: Run<S>,
+    conditions: Vec<Condition>,
+    enabled: bool,
+}
+
+impl<S: Set> Entry<S> {
+    fn should_run(&mut self, resources: &Resources) -> bool {
+        self.enabled && self.conditions.iter_mut().all(|condition| condition(resources))
+    }
+}
+
+/// An error in the order systems are constrained to run in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// A constraint names a system which hasn't been added.
+    UnknownSystem(String),
+    /// A system must run before one in an earlier stage.
+    StageConflict {
+        before: String,
+        after: String,
+    },
+    /// Systems which must each run before the next, and the last before the first.
+    Cycle(Vec<String>),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScheduleError::UnknownSystem(ref name) => write!(f, "no system named `{}`", name),
+            ScheduleError::StageConflict { ref before, ref after } => {
+                write!(f, "`{}` must run before `{}`, which is in an earlier stage", before, after)
+            }
+            ScheduleError::Cycle(ref names) => {
+                write!(f, "systems must run in a cycle: ")?;
+                for name in names {
+                    write!(f, "`{}` -> ", name)?;
+                }
+                write!(f, "`{}`", names[0])
+            }
+        }
+    }
+}
+
+impl Error for ScheduleError {
+    fn description(&self) -> &str {
+        match *self {
+            ScheduleError::UnknownSystem(_) => "unknown system",
+            ScheduleError::StageConflict { .. } => "constraint across stages",
+            ScheduleError::Cycle(_) => "constraint cycle",
+        }
+    }
+}
+
+/// The order a scheduler runs its systems in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    stages: Vec<(String, Vec<Vec<String>>)>,
+}
+
+impl Plan {
+    /// The stages with their batches of systems, in the order they run.
+    pub fn stages(&self) -> &[(String, Vec<Vec<String>>)] {
+        &self.stages
+    }
+
+    /// The names of the systems in the order they run in deterministic mode.
+    pub fn order(&self) -> Vec<&str> {
+        self.stages.iter()
+            .flat_map(|(_, batches)| batches.iter())
+            .flat_map(|batch| batch.iter().map(|name| &name[..]))
+            .collect()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (stage, batches) in &self.stages {
+            writeln!(f, "stage {}", stage)?;
+            for (i, batch) in batches.iter().enumerate() {
+                writeln!(f, "    {}: {}", i, batch.join(", "))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Systems in stages, with constraints on their order.
+pub struct Scheduler<S: Set> {
+    stages: Vec<String>,
+    systems: Vec<Entry<S>>,
+    // pairs of systems by name, where the first must run before the second.
+    constraints: Vec<(String, String)>,
+    // the indices of the systems in each batch of each stage, once resolved.
+    batches: Option<Vec<Vec<Vec<usize>>>>,
+}
+
+impl<S: Set> Scheduler<S> {
+    /// A scheduler with no stages or systems.
+    pub fn new() -> Self {
+        Scheduler {
+            stages: Vec::new(),
+            systems: Vec::new(),
+            constraints: Vec::new(),
+            batches: None,
+        }
+    }
+
+    /// Add a stage to run after those already added.
+    ///
+    /// Panics if the name is already taken.
+    pub fn with_stage(mut self, name: &str) -> Self {
+        self.add_stage(name);
+        self
+    }
+
+    /// Add a stage to run after those already added.
+    ///
+    /// Panics if the name is already taken.
+    pub fn add_stage(&mut self, name: &str) {
+        assert!(!self.stages.iter().any(|s| s == name), "stage `{}` is already added", name);
+        self.stages.push(name.to_owned());
+        self.batches = None;
+    }
+
+    /// Add a system to the last stage, with a name for identifying it.
+    ///
+    /// Panics if the name is already taken.
+    pub fn with<T: System + 'static>(mut self, name: &str, system: T) -> Self {
+        self.add(name, system);
+        self
+    }
+
+    /// Add a system to the last stage, which is `DEFAULT_STAGE` if none were added.
+    ///
+    /// Panics if the name is already taken.
+    pub fn add<T: System + 'static>(&mut self, name: &str, system: T) {
+        if self.stages.is_empty() {
+            self.add_stage(DEFAULT_STAGE);
+        }
+
+        let stage = self.stages.len() - 1;
+        self.insert(stage, name, system);
+    }
+
+    /// Add a system to a stage.
+    ///
+    /// Panics if the stage doesn't exist or the name is already taken.
+    pub fn add_to<T: System + 'static>(&mut self, stage: &str, name: &str, system: T) {
+        let stage = self.stages.iter().position(|s| s == stage)
+            .unwrap_or_else(|| panic!("no stage named `{}`", stage));
+        self.insert(stage, name, system);
+    }
+
+    fn insert<T: System + 'static>(&mut self, stage: usize, name: &str, mut system: T) {
+        assert!(!self.systems.iter().any(|s| s.name == name), "system `{}` is already added", name);
+
+        self.systems.push(Entry {
+            name: name.to_owned(),
+            stage: stage,
+            run: Box::new(move |wh| system.process(wh)),
+            conditions: Vec::new(),
+            enabled: true,
+        });
+        self.batches = None;
+    }
+
+    /// Make one system run before another. The systems needn't be added yet.
+    pub fn before(&mut self, system: &str, other: &str) {
+        self.constraints.push((system.to_owned(), other.to_owned()));
+        self.batches = None;
+    }
+
+    /// Make one system run after another. The systems needn't be added yet.
+    pub fn after(&mut self, system: &str, other: &str) {
+        self.before(other, system);
+    }
+
+    /// Only run a system when a condition on the resources holds. Conditions are
+    /// checked in the order they were added, until one of them doesn't hold.
+    pub fn run_if<F>(&mut self, system: &str, condition: F) -> Result<(), ScheduleError>
+    where F: FnMut(&Resources) -> bool + Send + 'static {
+        let entry = self.systems.iter_mut().find(|s| s.name == system)
+            .ok_or_else(|| ScheduleError::UnknownSystem(system.to_owned()))?;
+        entry.conditions.push(Box::new(condition));
+        Ok(())
+    }
+
+    /// Enable or disable a system. Disabled systems are skipped, and their
+    /// conditions aren't checked.
+    pub fn set_enabled(&mut self, system: &str, enabled: bool) -> Result<(), ScheduleError> {
+        let entry = self.systems.iter_mut().find(|s| s.name == system)
+            .ok_or_else(|| ScheduleError::UnknownSystem(system.to_owned()))?;
+        entry.enabled = enabled;
+        Ok(())
+    }
+
+    /// Whether a system is enabled, or `None` if it doesn't exist.
+    pub fn is_enabled(&self, system: &str) -> Option<bool> {
+        self.systems.iter().find(|s| s.name == system).map(|s| s.enabled)
+    }
+
+    /// The names of the stages, in the order they run.
+    pub fn stages(&self) -> Vec<&str> {
+        self.stages.iter().map(|s| &s[..]).collect()
+    }
+
+    /// The names of the systems, in the order they were added.
+    pub fn names(&self) -> Vec<&str> {
+        self.systems.iter().map(|s| &s.name[..]).collect()
+    }
+
+    /// Resolve the order the systems run in.
+    pub fn plan(&self) -> Result<Plan, ScheduleError> {
+        let batches = self.resolve()?;
+        let stages = self.stages.iter().zip(batches).map(|(stage, batches)| {
+            let batches = batches.into_iter()
+                .map(|batch| batch.into_iter().map(|i| self.systems[i].name.clone()).collect())
+                .collect();
+            (stage.clone(), batches)
+        });
+
+        Ok(Plan { stages: stages.collect() })
+    }
+
+    /// Run every enabled system whose conditions hold once, cleaning up destroyed
+    /// entities after each stage.
+    ///
+    /// Panics if the systems can't be ordered, as described by `plan`.
+    pub fn run(&mut self, world: &mut World<S>) {
+        if self.batches.is_none() {
+            match self.resolve() {
+                Ok(batches) => self.batches = Some(batches),
+                Err(err) => panic!("can't schedule systems: {}", err),
+            }
+        }
+
+        let systems = &mut self.systems;
+        for stage in self.batches.as_ref().unwrap() {
+            {
+                let wh = world.handle();
+                for batch in stage {
+                    // the batch is sorted, so this keeps the order systems were added.
+                    let mut entries = Vec::new();
+                    for (i, system) in systems.iter_mut().enumerate() {
+                        if batch.binary_search(&i).is_ok() && system.should_run(world.resources()) {
+                            entries.push(system);
+                        }
+                    }
+
+                    if world.is_deterministic() {
+                        for system in &mut entries {
+                            (system.run)(wh);
+                        }
+                    } else {
+                        run_parallel(&mut entries, wh);
+                    }
+                }
+            }
+
+            world.maintain();
+        }
+    }
+
+    // sort each stage into batches by its constraints.
+    fn resolve(&self) -> Result<Vec<Vec<Vec<usize>>>, ScheduleError> {
+        let n = self.systems.len();
+        let find = |name: &str| {
+            self.systems.iter().position(|s| s.name == name)
+                .ok_or_else(|| ScheduleError::UnknownSystem(name.to_owned()))
+        };
+
+        // the systems each one must wait for.
+        let mut preds = vec![Vec::new(); n];
+        for (before, after) in &self.constraints {
+            let (b, a) = (find(before)?, find(after)?);
+            let (b_stage, a_stage) = (self.systems[b].stage, self.systems[a].stage);
+
+            if b_stage > a_stage {
+                return Err(ScheduleError::StageConflict { before: before.clone(), after: after.clone() })
+            } else if b_stage == a_stage {
+                preds[a].push(b);
+            }
+        }
+
+        let mut stages = Vec::new();
+        for stage in 0..self.stages.len() {
+            let mut left: Vec<usize> = (0..n).filter(|&i| self.systems[i].stage == stage).collect();
+            let mut batches = Vec::new();
+
+            while !left.is_empty() {
+                let (ready, blocked): (Vec<usize>, Vec<usize>) = left.iter()
+                    .partition(|&&i| preds[i].iter().all(|p| !left.contains(p)));
+
+                if ready.is_empty() {
+                    return Err(ScheduleError::Cycle(self.find_cycle(&blocked, &preds)))
+                }
+
+                batches.push(ready);
+                left = blocked;
+            }
+
+            stages.push(batches);
+        }
+
+        Ok(stages)
+    }
+
+    // every blocked system waits on another blocked one, so following them
+    // back from any of them must come round to a system already seen.
+    fn find_cycle(&self, blocked: &[usize], preds: &[Vec<usize>]) -> Vec<String> {
+        let mut path = vec![blocked[0]];
+        loop {
+            let last = *path.last().unwrap();
+            let next = *preds[last].iter().find(|p| blocked.contains(p)).unwrap();
+
+            if let Some(start) = path.iter().position(|&i| i == next) {
+                // the path runs backwards, from each system to one it waits on.
+                return path[start..].iter().rev().map(|&i| self.systems[i].name.clone()).collect()
+            }
+
+            path.push(next);
+        }
+    }
+}
+
+impl<S: Set> Default for Scheduler<S> {
+    fn default() -> Self {
+        Scheduler::new()
+    }
+}
+
+// split the systems in half until there's only one left on each thread.
+fn run_parallel<S: Set>(systems: &mut [&mut Entry<S>], wh: WorldHandle<S>) {
+    if systems.len() > 1 {
+        let mid = systems.len() / 2;
+        let (left, right) = systems.split_at_mut(mid);
+        ::rayon::join(|| run_parallel(left, wh), || run_parallel(right, wh));
+    } else if let Some(system) = systems.first_mut() {
+        (system.run)(wh);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    struct Position;
+    impl Component for Position { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Position }
+    }
+
+    // records its name, and the ids it sees, into a shared log.
+    struct Log(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl System for Log {
+        fn process<'a, S: 'a + Set>(&mut self, wh: WorldHandle<'a, S>) {
+            let ids = wh.query::<(Position,)>().for_each(|e, _| e.id());
+            self.1.lock().unwrap().push(format!("{} {:?}", self.0, ids));
+        }
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let mut world = World::new(TestSet::new());
+        world.set_deterministic(true);
+
+        let es: Vec<Entity> = (0..3).map(|_| world.entities.next()).collect();
+        for &e in es.iter().rev() {
+            let v = world.entities.verify(e).unwrap();
+            world.data.get_storage_mut::<Position>().set(v, Position);
+        }
+
+        let mut scheduler = Scheduler::new()
+            .with("first", Log("first", log.clone()))
+            .with("second", Log("second", log.clone()));
+        scheduler.add("third", Log("third", log.clone()));
+        assert_eq!(scheduler.names(), vec!["first", "second", "third"]);
+
+        scheduler.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), vec!["first [0, 1, 2]", "second [0, 1, 2]", "third [0, 1, 2]"]);
+
+        // in parallel every system still runs once.
+        world.set_deterministic(false);
+        scheduler.run(&mut world);
+        assert_eq!(log.lock().unwrap().len(), 6);
+    }
+
+    // logs its name, for checking the order systems ran in.
+    struct Named(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl System for Named {
+        fn process<'a, S: 'a + Set>(&mut self, _: WorldHandle<'a, S>) {
+            self.1.lock().unwrap().push(self.0.to_owned());
+        }
+    }
+
+    #[test]
+    fn stages_and_constraints() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let named = |name| Named(name, log.clone());
+
+        let mut scheduler = Scheduler::new()
+            .with_stage("input")
+            .with("read_input", named("read_input"))
+            .with_stage("gameplay")
+            .with("combat", named("combat"))
+            .with("movement", named("movement"))
+            .with("ai", named("ai"))
+            .with_stage("render");
+        scheduler.add_to("render", "draw", named("draw"));
+        scheduler.before("ai", "movement");
+        scheduler.after("combat", "movement");
+        scheduler.before("read_input", "draw");
+
+        let plan = scheduler.plan().unwrap();
+        assert_eq!(plan.order(), vec!["read_input", "ai", "movement", "combat", "draw"]);
+        assert_eq!(plan.to_string(), "\
+stage input
+    0: read_input
+stage gameplay
+    0: ai
+    1: movement
+    2: combat
+stage render
+    0: draw
+");
+
+        let mut world = World::new(TestSet::new());
+        world.set_deterministic(true);
+        scheduler.run(&mut world);
+        assert_eq!(*log.lock().unwrap(), plan.order());
+
+        // constraints are checked when planning.
+        scheduler.before("draw", "read_input");
+        assert_eq!(scheduler.plan(), Err(ScheduleError::StageConflict {
+            before: "draw".to_owned(),
+            after: "read_input".to_owned(),
+        }));
+
+        let mut scheduler = Scheduler::<TestSet>::new()
+            .with("a", named("a"))
+            .with("b", named("b"))
+            .with("c", named("c"))
+            .with("d", named("d"));
+        scheduler.before("a", "b");
+        scheduler.before("b", "c");
+        scheduler.before("c", "b");
+        scheduler.before("d", "nowhere");
+        assert_eq!(scheduler.plan(), Err(ScheduleError::UnknownSystem("nowhere".to_owned())));
+
+        scheduler.constraints.pop();
+        let err = scheduler.plan().unwrap_err();
+        assert_eq!(err, ScheduleError::Cycle(vec!["c".to_owned(), "b".to_owned()]));
+        assert_eq!(err.to_string(), "systems must run in a cycle: `c` -> `b` -> `c`");
+    }
+
+    // whether the game is paused.
+    struct Paused(bool);
+
+    #[test]
+    fn conditions_and_enabling() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let named = |name| Named(name, log.clone());
+
+        let mut scheduler = Scheduler::new()
+            .with("physics", named("physics"))
+            .with("autosave", named("autosave"))
+            .with("debug", named("debug"));
+
+        scheduler.run_if("physics", |res| !res.lock::<Paused>().is_some_and(|p| p.0)).unwrap();
+        let mut runs = 0;
+        scheduler.run_if("autosave", move |_| { runs += 1; runs % 2 == 0 }).unwrap();
+        scheduler.set_enabled("debug", false).unwrap();
+        assert_eq!(scheduler.is_enabled("debug"), Some(false));
+        assert_eq!(scheduler.set_enabled("nowhere", true), Err(ScheduleError::UnknownSystem("nowhere".to_owned())));
+        assert_eq!(scheduler.is_enabled("nowhere"), None);
+        assert_eq!(scheduler.run_if("nowhere", |_| true), Err(ScheduleError::UnknownSystem("nowhere".to_owned())));
+
+        let mut world = World::new(TestSet::new());
+        world.set_deterministic(true);
+        scheduler.run(&mut world);
+        world.resources_mut().insert(Paused(true));
+        scheduler.run(&mut world);
+        scheduler.set_enabled("debug", true).unwrap();
+        world.resources_mut().insert(Paused(false));
+        scheduler.run(&mut world);
+
+        assert_eq!(*log.lock().unwrap(), vec!["physics", "autosave", "physics", "debug"]);
+    }
+}
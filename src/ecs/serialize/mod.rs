@@ -0,0 +1,764 @@
+//! Saving and loading worlds in a compact, versioned binary format.
+//!
+//! A `Registry` lists the components to save. A saved world holds the entity
+//! manager's generations and free list, then the data of each registered
+//! component in its reflected form.
+//!
+//! There are two ways to read a world back:
+//!
+//! * `Registry::load` restores a saved world exactly, so that entity handles
+//!   kept from before the save stay valid. This is meant for savegames.
+//! * `Registry::merge` spawns fresh entities for the saved ones in an existing
+//!   world, remapping the entity handles within components to match.
+//!   This is meant for level files and the like.
+//!
+//! # Format
+//!
+//! All integers are LEB128 varints, signed ones zigzag-encoded first.
+//! Floats are 8 bytes, little-endian.
+//!
+//! ```text
+//! magic      b"SNRK"
+//! version    varint
+//! entities   gens: count, then a byte per entity id
+//!            free list: count, then an id per entry
+//! components count, then per component:
+//!            type name, count, then per entity: entity, value
+//! ```
+//!
+//! Values are tagged with a byte for their kind.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::*;
+use super::reflect::{ReflectError, Value};
+use super::set::Set;
+
+pub mod prefab;
+pub mod record;
+pub mod replicate;
+pub mod scene;
+
+use self::prefab::{Prefab, PrefabError};
+
+/// The magic bytes at the start of every saved world.
+pub const MAGIC: &[u8; 4] = b"SNRK";
+
+/// The version of the format written by this library.
+pub const VERSION: u32 = 1;
+
+// value tags.
+const UNIT: u8 = 0;
+const BOOL: u8 = 1;
+const INT: u8 = 2;
+const UINT: u8 = 3;
+const FLOAT: u8 = 4;
+const STR: u8 = 5;
+const ENTITY: u8 = 6;
+const NONE: u8 = 7;
+const SOME: u8 = 8;
+const LIST: u8 = 9;
+const TUPLE: u8 = 10;
+const STRUCT: u8 = 11;
+
+// how deeply values may nest, so corrupt data can't overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+/// An error when reading a saved world.
+#[derive(Debug)]
+pub enum LoadError {
+    /// The underlying reader failed, or the data ended early.
+    Io(io::Error),
+    /// The data doesn't start with the magic bytes.
+    BadMagic,
+    /// The data was written by a newer version of the format.
+    UnsupportedVersion(u32),
+    /// The data is malformed.
+    Corrupt(&'static str),
+    /// A component in the data isn't registered.
+    UnknownComponent(String),
+    /// A component's data couldn't be converted back.
+    Reflect(&'static str, ReflectError),
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LoadError::Io(ref err) => write!(f, "I/O error: {}", err),
+            LoadError::BadMagic => write!(f, "not a saved world"),
+            LoadError::UnsupportedVersion(v) =>
+                write!(f, "format version {} is newer than {}", v, VERSION),
+            LoadError::Corrupt(what) => write!(f, "corrupt data: {}", what),
+            LoadError::UnknownComponent(ref name) => write!(f, "unregistered component `{}`", name),
+            LoadError::Reflect(name, ref err) => write!(f, "bad data for `{}`: {}", name, err),
+        }
+    }
+}
+
+impl Error for LoadError {
+    fn description(&self) -> &str {
+        match *self {
+            LoadError::Io(_) => "I/O error",
+            LoadError::BadMagic => "not a saved world",
+            LoadError::UnsupportedVersion(_) => "unsupported format version",
+            LoadError::Corrupt(_) => "corrupt data",
+            LoadError::UnknownComponent(_) => "unregistered component",
+            LoadError::Reflect(..) => "bad component data",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            LoadError::Io(ref err) => Some(err),
+            LoadError::Reflect(_, ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+// functions for one registered component.
+struct Entry<S: Set> {
+    name: &'static str,
+    // the reflected data of every living entity with the component.
+    values: fn(&S, &EntityManager) -> Vec<(Entity, Value)>,
+    // the version of the storage, which changes along with its data.
+    version: fn(&S) -> usize,
+    // convert a value and set it as an entity's data.
+    insert: fn(&mut S, VerifiedEntity, Value) -> Result<(), ReflectError>,
+    // remove an entity's data.
+    remove: fn(&mut S, VerifiedEntity),
+    // whether a value converts.
+    check: fn(Value) -> Result<(), ReflectError>,
+}
+
+// components read from saved data: their entry, the saved entity and the value.
+type Components<'a, S> = Vec<(&'a Entry<S>, Entity, Value)>;
+
+/// The components of a set which are saved and loaded.
+pub struct Registry<S: Set> {
+    entries: Vec<Entry<S>>,
+}
+
+impl<S: Set> Registry<S> {
+    /// Create a registry with no components.
+    pub fn new() -> Self {
+        Registry { entries: Vec::new() }
+    }
+
+    /// Register a component. Components are saved by their reflected type name,
+    /// so no two registered components may share one.
+    pub fn register<T: SerializeComponent>(mut self) -> Self {
+        assert!(self.find(T::type_name()).is_none(), "component `{}` registered twice", T::type_name());
+
+        self.entries.push(Entry {
+            name: T::type_name(),
+            values: storage_values::<T, S>,
+            version: storage_version::<T, S>,
+            insert: insert_value::<T, S>,
+            remove: remove_value::<T, S>,
+            check: check_value::<T>,
+        });
+        self
+    }
+
+    /// Write a world.
+    pub fn save<W: Write>(&self, world: &World<S>, writer: &mut W) -> io::Result<()> {
+        let mut enc = Encoder { out: Vec::new() };
+        enc.out.extend_from_slice(MAGIC);
+        enc.uint(VERSION as u64);
+
+        let entities = &world.entities;
+        enc.uint(entities.gens.len() as u64);
+        enc.out.extend_from_slice(&entities.gens);
+        enc.uint(entities.unused.len() as u64);
+        for &id in &entities.unused {
+            enc.uint(id as u64);
+        }
+
+        enc.uint(self.entries.len() as u64);
+        for entry in &self.entries {
+            let values = (entry.values)(&world.data, entities);
+            enc.string(entry.name);
+            enc.uint(values.len() as u64);
+            for (e, value) in values {
+                enc.entity(e);
+                enc.value(&value);
+            }
+        }
+
+        writer.write_all(&enc.out)
+    }
+
+    /// Read a world exactly as it was saved, storing components in the given set.
+    pub fn load<R: Read>(&self, data: S, reader: &mut R) -> Result<World<S>, LoadError> {
+        let mut dec = Decoder::new(reader)?;
+        let mut world = World::new(data);
+
+        let (gens, free) = read_entities(&mut dec)?;
+        world.entities.gens = gens;
+        world.entities.unused.extend(free);
+
+        let components = self.read_components(&mut dec, &|e| world.entities.is_alive(e))?;
+        self.insert_components(&mut world, components, &mut |e| e)?;
+        Ok(world)
+    }
+
+    /// Read a saved world into an existing one, returning a map
+    /// from the saved entities to the ones spawned for them.
+    ///
+    /// Entity handles within components are remapped. Handles to entities which
+    /// were already dead when saved are remapped to a dead entity.
+    ///
+    /// All of the data is read and checked before anything is spawned,
+    /// so the world is left unchanged on error.
+    pub fn merge<R: Read>(&self, world: &mut World<S>, reader: &mut R)
+    -> Result<HashMap<Entity, Entity>, LoadError> {
+        let mut dec = Decoder::new(reader)?;
+
+        let (gens, free) = read_entities(&mut dec)?;
+        let mut is_free = vec![false; gens.len()];
+        for id in free {
+            is_free[id as usize] = true;
+        }
+
+        // every id which isn't free was alive when saved.
+        let saved: Vec<Entity> = gens.iter().enumerate()
+            .filter(|&(id, _)| !is_free[id])
+            .map(|(id, &gen)| Entity::new(gen, id as u32))
+            .collect();
+
+        let alive: HashSet<Entity> = saved.iter().cloned().collect();
+        let components = self.read_components(&mut dec, &|e| alive.contains(&e))?;
+
+        let map: HashMap<Entity, Entity> = saved.into_iter().map(|e| (e, world.entities.next())).collect();
+        let dead = world.entities.next();
+        world.entities.destroy(dead);
+
+        self.insert_components(world, components, &mut |e| *map.get(&e).unwrap_or(&dead))?;
+        Ok(map)
+    }
+
+    // read every component's data, checking that it belongs to an entity
+    // which was alive when saved and converts back.
+    fn read_components(&self, dec: &mut Decoder, alive: &Fn(Entity) -> bool)
+    -> Result<Components<S>, LoadError> {
+        let mut out = Vec::new();
+        for _ in 0..dec.len()? {
+            let name = dec.string()?;
+            let entry = self.find(&name).ok_or(LoadError::UnknownComponent(name))?;
+
+            for _ in 0..dec.len()? {
+                let e = dec.entity()?;
+                if !alive(e) { return Err(LoadError::Corrupt("component of a dead entity")) }
+
+                let value = dec.value()?;
+                (entry.check)(value.clone()).map_err(|err| LoadError::Reflect(entry.name, err))?;
+                out.push((entry, e, value));
+            }
+        }
+
+        Ok(out)
+    }
+
+    // set components read by `read_components`, remapping the entities they belong to and refer to.
+    fn insert_components(&self, world: &mut World<S>, components: Components<S>,
+                         map: &mut FnMut(Entity) -> Entity) -> Result<(), LoadError> {
+        for (entry, e, mut value) in components {
+            let e = world.entities.verify(map(e)).unwrap();
+            value.map_entities(&mut |e| map(e));
+            (entry.insert)(&mut world.data, e, value).map_err(|err| LoadError::Reflect(entry.name, err))?;
+        }
+
+        Ok(())
+    }
+
+    fn find(&self, name: &str) -> Option<&Entry<S>> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    // like `validate`, but also gives the path to the component with the error:
+    // the indices of the children leading to it, and the component's index.
+    fn check_prefab(&self, prefab: &Prefab) -> Result<(), (Vec<usize>, PrefabError)> {
+        for (idx, (name, value)) in prefab.components().iter().enumerate() {
+            let entry = self.find(name).ok_or((vec![idx], PrefabError::UnknownComponent(name.clone())))?;
+            (entry.check)(value.clone()).map_err(|err| (vec![idx], PrefabError::Reflect(entry.name, err)))?;
+        }
+
+        for (idx, child) in prefab.children().iter().enumerate() {
+            self.check_prefab(child).map_err(|(mut path, err)| {
+                path.insert(0, idx);
+                (path, err)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    // insert a prefab's components into a living entity and spawn its children,
+    // remapping entity handles in the data. new entities are added to `spawned`.
+    fn insert_prefab(&self, world: &mut World<S>, e: Entity, prefab: &Prefab,
+                     map: &mut FnMut(Entity) -> Entity, spawned: &mut Vec<Entity>)
+    -> Result<(), (Vec<usize>, PrefabError)> {
+        for (idx, (name, value)) in prefab.components().iter().enumerate() {
+            let entry = self.find(name).ok_or((vec![idx], PrefabError::UnknownComponent(name.clone())))?;
+
+            let mut value = value.clone();
+            value.map_entities(&mut |e| map(e));
+
+            let verified = world.entities.verify(e).unwrap();
+            (entry.insert)(&mut world.data, verified, value)
+                .map_err(|err| (vec![idx], PrefabError::Reflect(entry.name, err)))?;
+        }
+
+        for (idx, child) in prefab.children().iter().enumerate() {
+            let c = world.entities.next();
+            spawned.push(c);
+
+            self.insert_prefab(world, c, child, map, spawned).map_err(|(mut path, err)| {
+                path.insert(0, idx);
+                (path, err)
+            })?;
+            world.set_parent(c, e).unwrap();
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Set> Default for Registry<S> {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+// read the entity generations and the free list, checking that
+// each free id is in range and listed once.
+fn read_entities(dec: &mut Decoder) -> Result<(Vec<u8>, Vec<u32>), LoadError> {
+    let gens = dec.bytes()?;
+    let mut listed = vec![false; gens.len()];
+    let mut free = Vec::new();
+    for _ in 0..dec.len()? {
+        let id = dec.u32()?;
+        match listed.get_mut(id as usize) {
+            Some(&mut true) => return Err(LoadError::Corrupt("duplicate free entity id")),
+            Some(listed) => *listed = true,
+            None => return Err(LoadError::Corrupt("free entity id")),
+        }
+        free.push(id);
+    }
+
+    Ok((gens, free))
+}
+
+fn storage_values<T: SerializeComponent, S: Set>(set: &S, entities: &EntityManager) -> Vec<(Entity, Value)> {
+    let storage = set.lock_storage::<T>();
+    let values = storage.entities()
+        .filter_map(|e| entities.verify(e))
+        .map(|e| (e.entity(), storage.get(e).unwrap().to_value()))
+        .collect();
+    values
+}
+
+fn storage_version<T: SerializeComponent, S: Set>(set: &S) -> usize {
+    set.storage_version::<T>()
+}
+
+fn insert_value<T: SerializeComponent, S: Set>(set: &mut S, e: VerifiedEntity, value: Value)
+-> Result<(), ReflectError> {
+    set.get_storage_mut::<T>().set(e, T::from_value(value)?);
+    Ok(())
+}
+
+fn remove_value<T: SerializeComponent, S: Set>(set: &mut S, e: VerifiedEntity) {
+    set.get_storage_mut::<T>().remove(e);
+}
+
+fn check_value<T: SerializeComponent>(value: Value) -> Result<(), ReflectError> {
+    T::from_value(value).map(|_| ())
+}
+
+struct Encoder {
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn uint(&mut self, mut n: u64) {
+        loop {
+            let byte = (n & 0x7f) as u8;
+            n >>= 7;
+            if n == 0 {
+                self.out.push(byte);
+                return
+            }
+
+            self.out.push(byte | 0x80);
+        }
+    }
+
+    fn int(&mut self, n: i64) {
+        self.uint(((n << 1) ^ (n >> 63)) as u64)
+    }
+
+    fn string(&mut self, s: &str) {
+        self.uint(s.len() as u64);
+        self.out.extend_from_slice(s.as_bytes());
+    }
+
+    fn entity(&mut self, e: Entity) {
+        self.uint(e.id as u64)
+    }
+
+    fn value(&mut self, value: &Value) {
+        match *value {
+            Value::Unit => self.out.push(UNIT),
+            Value::Bool(b) => {
+                self.out.push(BOOL);
+                self.out.push(b as u8);
+            }
+            Value::Int(n) => {
+                self.out.push(INT);
+                self.int(n);
+            }
+            Value::UInt(n) => {
+                self.out.push(UINT);
+                self.uint(n);
+            }
+            Value::Float(x) => {
+                self.out.push(FLOAT);
+                let bits = x.to_bits();
+                for i in 0..8 {
+                    self.out.push((bits >> (i * 8)) as u8);
+                }
+            }
+            Value::Str(ref s) => {
+                self.out.push(STR);
+                self.string(s);
+            }
+            Value::Entity(e) => {
+                self.out.push(ENTITY);
+                self.entity(e);
+            }
+            Value::Option(None) => self.out.push(NONE),
+            Value::Option(Some(ref v)) => {
+                self.out.push(SOME);
+                self.value(v);
+            }
+            Value::List(ref vs) | Value::Tuple(ref vs) => {
+                self.out.push(if let Value::List(_) = *value { LIST } else { TUPLE });
+                self.uint(vs.len() as u64);
+                for v in vs { self.value(v) }
+            }
+            Value::Struct(ref fields) => {
+                self.out.push(STRUCT);
+                self.uint(fields.len() as u64);
+                for (name, v) in fields {
+                    self.string(name);
+                    self.value(v);
+                }
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    reader: &'a mut Read,
+    // values currently being decoded.
+    depth: usize,
+}
+
+impl<'a> Decoder<'a> {
+    // check the header and start decoding.
+    fn new<R: Read>(reader: &'a mut R) -> Result<Self, LoadError> {
+        let mut dec = Decoder { reader: reader, depth: 0 };
+
+        let mut magic = [0; 4];
+        dec.reader.read_exact(&mut magic)?;
+        if &magic != MAGIC { return Err(LoadError::BadMagic) }
+
+        let version = dec.u32()?;
+        if version > VERSION { return Err(LoadError::UnsupportedVersion(version)) }
+
+        Ok(dec)
+    }
+
+    fn byte(&mut self) -> Result<u8, LoadError> {
+        let mut buf = [0];
+        self.reader.read_exact(&mut buf)?;
+        Ok(buf[0])
+    }
+
+    fn uint(&mut self) -> Result<u64, LoadError> {
+        let mut n = 0;
+        for shift in 0..10 {
+            let byte = self.byte()?;
+            n |= ((byte & 0x7f) as u64) << (shift * 7);
+            if byte & 0x80 == 0 { return Ok(n) }
+        }
+
+        Err(LoadError::Corrupt("varint too long"))
+    }
+
+    fn int(&mut self) -> Result<i64, LoadError> {
+        let n = self.uint()?;
+        Ok(((n >> 1) as i64) ^ -((n & 1) as i64))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        let n = self.uint()?;
+        if n > u32::MAX as u64 { return Err(LoadError::Corrupt("integer out of range")) }
+        Ok(n as u32)
+    }
+
+    fn len(&mut self) -> Result<usize, LoadError> {
+        self.u32().map(|n| n as usize)
+    }
+
+    fn bytes(&mut self) -> Result<Vec<u8>, LoadError> {
+        let len = self.len()?;
+        let mut buf = Vec::new();
+        (&mut self.reader).take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len { return Err(LoadError::Corrupt("data ended early")) }
+        Ok(buf)
+    }
+
+    fn string(&mut self) -> Result<String, LoadError> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes).map_err(|_| LoadError::Corrupt("invalid utf-8"))
+    }
+
+    fn entity(&mut self) -> Result<Entity, LoadError> {
+        self.u32().map(|id| Entity { id: id })
+    }
+
+    fn value(&mut self) -> Result<Value, LoadError> {
+        if self.depth == MAX_DEPTH { return Err(LoadError::Corrupt("values nested too deeply")) }
+
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Value, LoadError> {
+        Ok(match self.byte()? {
+            UNIT => Value::Unit,
+            BOOL => Value::Bool(self.byte()? != 0),
+            INT => Value::Int(self.int()?),
+            UINT => Value::UInt(self.uint()?),
+            FLOAT => {
+                let mut buf = [0; 8];
+                self.reader.read_exact(&mut buf)?;
+                let bits = buf.iter().rev().fold(0, |bits, &b| (bits << 8) | b as u64);
+                Value::Float(f64::from_bits(bits))
+            }
+            STR => Value::Str(self.string()?),
+            ENTITY => Value::Entity(self.entity()?),
+            NONE => Value::Option(None),
+            SOME => Value::Option(Some(Box::new(self.value()?))),
+            tag @ LIST | tag @ TUPLE => {
+                let mut vs = Vec::new();
+                for _ in 0..self.len()? {
+                    vs.push(self.value()?);
+                }
+
+                if tag == LIST { Value::List(vs) } else { Value::Tuple(vs) }
+            }
+            STRUCT => {
+                let mut fields = Vec::new();
+                for _ in 0..self.len()? {
+                    fields.push((self.string()?, self.value()?));
+                }
+
+                Value::Struct(fields)
+            }
+            _ => return Err(LoadError::Corrupt("unknown value tag")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::hierarchy::Parent;
+    use super::super::reflect::{Fields, Reflect, ReflectError, Value};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Unit {
+        name: String,
+        health: i32,
+        target: Option<Entity>,
+    }
+
+    impl Component for Unit { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Unit {}
+
+    impl Reflect for Unit {
+        fn type_name() -> &'static str { "Unit" }
+
+        fn to_value(&self) -> Value {
+            Value::Struct(vec![
+                ("name".to_owned(), self.name.to_value()),
+                ("health".to_owned(), self.health.to_value()),
+                ("target".to_owned(), self.target.to_value()),
+            ])
+        }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            let mut fields = Fields::new(value)?;
+            let out = Unit {
+                name: fields.take("name")?,
+                health: fields.take("health")?,
+                target: fields.take("target")?,
+            };
+            fields.finish()?;
+            Ok(out)
+        }
+    }
+
+    component_set! {
+        struct TestSet { Unit, Parent }
+    }
+
+    fn registry() -> Registry<TestSet> {
+        Registry::new().register::<Unit>().register::<Parent>()
+    }
+
+    fn unit(world: &World<TestSet>, e: Entity) -> Option<Unit> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Unit>().get(e).cloned()
+    }
+
+    #[test]
+    fn save_load_and_merge() {
+        let mut world = World::new(TestSet::new());
+        let gone = world.build_entity().spawn();
+        let a = world.build_entity().spawn();
+        let b = world.build_entity().with(Unit { name: "b".to_owned(), health: -3, target: Some(a) }).spawn();
+        let c = world.build_entity().with(Unit { name: "c".to_owned(), health: 7, target: Some(gone) }).spawn();
+        world.set_parent(c, b).unwrap();
+        world.despawn(gone);
+
+        let mut bytes = Vec::new();
+        registry().save(&world, &mut bytes).unwrap();
+
+        // loading keeps handles as they were.
+        let loaded = registry().load(TestSet::new(), &mut &bytes[..]).unwrap();
+        assert!(!loaded.entities.is_alive(gone));
+        assert_eq!(unit(&loaded, b), unit(&world, b));
+        assert_eq!(loaded.children(b), vec![c]);
+
+        // merging spawns new entities and remaps handles.
+        let mut other = World::new(TestSet::new());
+        let existing = other.build_entity().spawn();
+        let map = registry().merge(&mut other, &mut &bytes[..]).unwrap();
+        assert_eq!(map.len(), 3);
+        assert!(!map.values().any(|&e| e == existing));
+
+        assert_eq!(unit(&other, map[&b]).unwrap().target, Some(map[&a]));
+        assert_eq!(other.parent(map[&c]), Some(map[&b]));
+
+        let dangling = unit(&other, map[&c]).unwrap().target.unwrap();
+        assert!(!other.entities.is_alive(dangling));
+
+        // bad input.
+        match registry().load(TestSet::new(), &mut &b"SNRK\x02"[..]) {
+            Err(LoadError::UnsupportedVersion(2)) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+
+        let partial = Registry::new().register::<Unit>();
+        match partial.load(TestSet::new(), &mut &bytes[..]) {
+            Err(LoadError::UnknownComponent(ref name)) if name == "Parent" => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+
+        assert!(registry().load(TestSet::new(), &mut &bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn failed_merges_leave_the_world_alone() {
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().with(Unit { name: "a".to_owned(), health: 1, target: None }).spawn();
+        let b = world.build_entity().with(Unit { name: "b".to_owned(), health: 2, target: Some(a) }).spawn();
+        world.set_parent(b, a).unwrap();
+
+        let mut bytes = Vec::new();
+        registry().save(&world, &mut bytes).unwrap();
+
+        // a header with two living entities, and units for them: the first fine, the second not.
+        let mut enc = Encoder { out: Vec::new() };
+        enc.out.extend_from_slice(MAGIC);
+        enc.uint(VERSION as u64);
+        enc.string("\0\0");
+        enc.uint(0);
+        enc.uint(1);
+        enc.string("Unit");
+        enc.uint(2);
+        enc.entity(Entity::new(0, 0));
+        enc.value(&Unit { name: "x".to_owned(), health: 3, target: None }.to_value());
+        enc.entity(Entity::new(0, 1));
+        enc.value(&Value::Int(3));
+
+        let mut other = World::new(TestSet::new());
+        let existing = other.build_entity().with(Unit { name: "e".to_owned(), health: 0, target: None }).spawn();
+        let entities = other.entities.clone();
+
+        assert!(registry().merge(&mut other, &mut &bytes[..bytes.len() - 1]).is_err());
+        match registry().merge(&mut other, &mut &enc.out[..]) {
+            Err(LoadError::Reflect("Unit", _)) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+
+        assert_eq!(other.entities.alive(), vec![existing]);
+        assert_eq!(other.entities.clone().next(), entities.clone().next());
+        assert_eq!(other.data.lock_storage::<Unit>().entities().count(), 1);
+        assert_eq!(other.data.lock_storage::<Parent>().entities().count(), 0);
+
+        // a free list naming an id twice.
+        let mut enc = Encoder { out: Vec::new() };
+        enc.out.extend_from_slice(MAGIC);
+        enc.uint(VERSION as u64);
+        enc.string("\0\0");
+        enc.uint(2);
+        enc.uint(1);
+        enc.uint(1);
+        enc.uint(0);
+        match registry().load(TestSet::new(), &mut &enc.out[..]) {
+            Err(LoadError::Corrupt("duplicate free entity id")) => {}
+            other => panic!("unexpected {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn nesting_limit() {
+        fn decode(bytes: &[u8]) -> Result<Value, LoadError> {
+            let mut reader = bytes;
+            Decoder { reader: &mut reader, depth: 0 }.value()
+        }
+
+        let mut nested = vec![SOME; MAX_DEPTH - 1];
+        nested.push(UNIT);
+        assert!(decode(&nested).is_ok());
+
+        // a single-element list in each option, far deeper than the limit.
+        let mut deep = Vec::new();
+        for _ in 0..100000 {
+            deep.extend_from_slice(&[SOME, LIST, 1]);
+        }
+        deep.push(UNIT);
+
+        match decode(&deep) {
+            Err(LoadError::Corrupt(_)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Reusable templates for entities.
+//!
+//! A `Prefab` holds the reflected data of a set of components, along with
+//! prefabs for child entities. Prefabs are instantiated through a `Registry`,
+//! optionally with overrides for that one instance. Instantiating a prefab with
+//! children requires `Parent` to be in the world's set.
+//!
+//! A prefab can inherit from a base prefab and override parts of it. Overriding a
+//! component with a struct value only replaces the fields given, so a derived prefab
+//! can change a single field of a component:
+//!
+//! ```
+//! # use snorkium::ecs::serialize::prefab::Prefab;
+//! # use snorkium::ecs::reflect::Value;
+//! let goblin = Prefab::new().with_value("Stats", Value::Struct(vec![
+//!     ("health".to_owned(), Value::UInt(10)),
+//!     ("damage".to_owned(), Value::UInt(2)),
+//! ]));
+//!
+//! let chief = Prefab::inherit(&goblin)
+//!     .with_value("Stats", Value::Struct(vec![("damage".to_owned(), Value::UInt(5))]));
+//!
+//! assert_eq!(chief.get("Stats"), Some(&Value::Struct(vec![
+//!     ("health".to_owned(), Value::UInt(10)),
+//!     ("damage".to_owned(), Value::UInt(5)),
+//! ])));
+//! ```
+//!
+//! Prefabs can also be declared in the scene format, with `prefab` blocks.
+
+use std::error::Error;
+use std::fmt;
+
+use super::*;
+
+/// A template for an entity and its children.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Prefab {
+    components: Vec<(String, Value)>,
+    children: Vec<Prefab>,
+}
+
+impl Prefab {
+    /// A prefab with no components or children.
+    pub fn new() -> Self {
+        Prefab::default()
+    }
+
+    /// A prefab with everything in the base, to be overridden.
+    pub fn inherit(base: &Prefab) -> Self {
+        base.clone()
+    }
+
+    /// Add a component, overriding any the prefab already has.
+    pub fn with<T: SerializeComponent>(self, data: T) -> Self {
+        self.with_value(T::type_name(), data.to_value())
+    }
+
+    /// Add a component by its registered name and reflected value.
+    ///
+    /// If the prefab already has the component, struct values are merged
+    /// into it field by field, and anything else replaces it.
+    pub fn with_value(mut self, name: &str, value: Value) -> Self {
+        match self.components.iter().position(|(n, _)| n == name) {
+            Some(idx) => patch(&mut self.components[idx].1, value),
+            None => self.components.push((name.to_owned(), value)),
+        }
+
+        self
+    }
+
+    /// Add a child entity.
+    pub fn with_child(mut self, child: Prefab) -> Self {
+        self.children.push(child);
+        self
+    }
+
+    /// Apply every component and child of another prefab as overrides.
+    pub fn merge(mut self, overrides: &Prefab) -> Self {
+        for (name, value) in &overrides.components {
+            self = self.with_value(name, value.clone());
+        }
+
+        self.children.extend(overrides.children.iter().cloned());
+        self
+    }
+
+    /// The value of a component.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.components.iter().find(|&(n, _)| n == name).map(|(_, value)| value)
+    }
+
+    /// The components, by name, in the order they were added.
+    pub fn components(&self) -> &[(String, Value)] {
+        &self.components
+    }
+
+    /// The prefabs for the child entities.
+    pub fn children(&self) -> &[Prefab] {
+        &self.children
+    }
+}
+
+// merge struct fields from the patch, replacing anything else.
+fn patch(target: &mut Value, value: Value) {
+    match (target, value) {
+        (&mut Value::Struct(ref mut fields), Value::Struct(patches)) => {
+            for (name, value) in patches {
+                match fields.iter().position(|(n, _)| *n == name) {
+                    Some(idx) => patch(&mut fields[idx].1, value),
+                    None => fields.push((name, value)),
+                }
+            }
+        }
+        (target, value) => *target = value,
+    }
+}
+
+/// An error when instantiating a prefab.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PrefabError {
+    /// A component in the prefab isn't registered.
+    UnknownComponent(String),
+    /// A component's data couldn't be converted.
+    Reflect(&'static str, ReflectError),
+}
+
+impl fmt::Display for PrefabError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PrefabError::UnknownComponent(ref name) => write!(f, "unregistered component `{}`", name),
+            PrefabError::Reflect(name, ref err) => write!(f, "bad data for `{}`: {}", name, err),
+        }
+    }
+}
+
+impl Error for PrefabError {
+    fn description(&self) -> &str {
+        match *self {
+            PrefabError::UnknownComponent(_) => "unregistered component",
+            PrefabError::Reflect(..) => "bad component data",
+        }
+    }
+}
+
+impl<S: Set> Registry<S> {
+    /// Spawn an entity, and its children, from a prefab.
+    ///
+    /// Nothing is spawned if the prefab has an error.
+    pub fn instantiate(&self, world: &mut World<S>, prefab: &Prefab) -> Result<Entity, PrefabError> {
+        let e = world.entities.next();
+        let mut spawned = vec![e];
+
+        match self.insert_prefab(world, e, prefab, &mut |e| e, &mut spawned) {
+            Ok(()) => Ok(e),
+            Err((_, err)) => {
+                for e in spawned {
+                    world.despawn(e);
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Spawn an entity from a prefab, with overrides for this instance.
+    pub fn instantiate_with(&self, world: &mut World<S>, prefab: &Prefab, overrides: &Prefab)
+    -> Result<Entity, PrefabError> {
+        self.instantiate(world, &prefab.clone().merge(overrides))
+    }
+
+    /// Check that every component in a prefab is registered and has valid data.
+    pub fn validate(&self, prefab: &Prefab) -> Result<(), PrefabError> {
+        self.check_prefab(prefab).map_err(|(_, err)| err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::hierarchy::Parent;
+    use super::super::super::reflect::{tuple_elements, Reflect};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Health(u32);
+
+    impl Component for Health { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Health {}
+
+    impl Reflect for Health {
+        fn type_name() -> &'static str { "Health" }
+        fn to_value(&self) -> Value { Value::Tuple(vec![self.0.to_value()]) }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            Ok(Health(Reflect::from_value(tuple_elements(value, 1)?.next().unwrap())?))
+        }
+    }
+
+    component_set! {
+        struct TestSet { Health, Parent }
+    }
+
+    fn health(world: &World<TestSet>, e: Entity) -> Option<u32> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Health>().get(e).map(|h| h.0)
+    }
+
+    #[test]
+    fn instantiate_with_overrides() {
+        let registry = Registry::new().register::<Health>().register::<Parent>();
+        let mut world = World::new(TestSet::new());
+
+        let goblin = Prefab::new().with(Health(10)).with_child(Prefab::new().with(Health(1)));
+        let chief = Prefab::inherit(&goblin).with(Health(30));
+        assert_eq!(registry.validate(&chief), Ok(()));
+
+        let a = registry.instantiate(&mut world, &goblin).unwrap();
+        let b = registry.instantiate(&mut world, &chief).unwrap();
+        let c = registry.instantiate_with(&mut world, &chief, &Prefab::new().with(Health(31))).unwrap();
+
+        assert_eq!(health(&world, a), Some(10));
+        assert_eq!(health(&world, b), Some(30));
+        assert_eq!(health(&world, c), Some(31));
+        assert_eq!(world.children(c).len(), 1);
+        assert_eq!(health(&world, world.children(c)[0]), Some(1));
+
+        // a bad child means nothing is spawned.
+        let bad = Prefab::inherit(&goblin).with_child(Prefab::new().with_value("Health", Value::Str("x".to_owned())));
+        let before = world.entities.alive().len();
+        match registry.instantiate(&mut world, &bad) {
+            Err(PrefabError::Reflect("Health", _)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(world.entities.alive().len(), before);
+        assert_eq!(registry.validate(&Prefab::new().with_value("Mana", Value::Unit)),
+                   Err(PrefabError::UnknownComponent("Mana".to_owned())));
+    }
+}
@@ -0,0 +1,325 @@
+//! Recording the inputs fed into a world, and replaying them.
+//!
+//! Everything from outside the simulation which changes the world, such as
+//! player commands, network events or resource writes, is expressed as an
+//! `Input`. Each tick, a `Recorder` applies that tick's inputs, runs the scheduler
+//! and writes the inputs to a log along with the resulting `World::state_hash`.
+//! Resources written by inputs should be inserted with `Resources::insert_hashed`,
+//! so that the hash covers them too.
+//!
+//! `replay` feeds a log back into a fresh world and scheduler, set up the same way
+//! as the recorded one, and checks the hash after every tick. The first tick where
+//! they differ is where the simulation stopped being deterministic. Both put
+//! the world into deterministic mode.
+//!
+//! # Format
+//!
+//! ```text
+//! magic      b"SNRI"
+//! version    varint
+//! ticks      per tick: 1, count, then a value per input, then the hash as 8 bytes, little-endian
+//! ```
+//!
+//! A log ends after any whole tick, so one cut short by a crash can still be replayed.
+
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use super::*;
+use super::super::reflect::Reflect;
+use super::super::schedule::Scheduler;
+
+const LOG_MAGIC: &[u8; 4] = b"SNRI";
+const TICK: u8 = 1;
+
+/// An input from outside the simulation.
+pub trait Input<S: Set>: Reflect {
+    /// Make the input's changes to the world.
+    fn apply(&self, world: &mut World<S>);
+}
+
+/// Applies inputs and runs ticks, writing a log to replay them from.
+pub struct Recorder<W: Write> {
+    writer: W,
+    ticks: usize,
+}
+
+impl<W: Write> Recorder<W> {
+    /// Start a log.
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        let mut enc = Encoder { out: LOG_MAGIC.to_vec() };
+        enc.uint(VERSION as u64);
+        writer.write_all(&enc.out)?;
+
+        Ok(Recorder { writer: writer, ticks: 0 })
+    }
+
+    /// Apply a tick's inputs in order, run the scheduler and log the tick,
+    /// returning the state hash after it.
+    pub fn tick<S: Set, I: Input<S>>(&mut self, world: &mut World<S>, scheduler: &mut Scheduler<S>, inputs: &[I])
+    -> io::Result<u64> {
+        let hash = step(world, scheduler, inputs);
+
+        let mut enc = Encoder { out: vec![TICK] };
+        enc.uint(inputs.len() as u64);
+        for input in inputs {
+            enc.value(&input.to_value());
+        }
+
+        for i in 0..8 {
+            enc.out.push((hash >> (i * 8)) as u8);
+        }
+
+        self.writer.write_all(&enc.out)?;
+        self.ticks += 1;
+        Ok(hash)
+    }
+
+    /// The number of ticks logged.
+    pub fn ticks(&self) -> usize {
+        self.ticks
+    }
+
+    /// Flush the log and return the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+// apply inputs and run the scheduler deterministically.
+fn step<S: Set, I: Input<S>>(world: &mut World<S>, scheduler: &mut Scheduler<S>, inputs: &[I]) -> u64 {
+    world.set_deterministic(true);
+    for input in inputs {
+        input.apply(world);
+    }
+
+    scheduler.run(world);
+    world.state_hash()
+}
+
+/// An error when replaying a log.
+#[derive(Debug)]
+pub enum ReplayError {
+    /// The log couldn't be read.
+    Load(LoadError),
+    /// The state after a tick, counting from zero, didn't match the log.
+    Diverged {
+        tick: usize,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ReplayError::Load(ref err) => write!(f, "bad log: {}", err),
+            ReplayError::Diverged { tick, expected, actual } => {
+                write!(f, "state diverged at tick {}: expected hash {:016x}, found {:016x}", tick, expected, actual)
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {
+    fn description(&self) -> &str {
+        match *self {
+            ReplayError::Load(_) => "bad log",
+            ReplayError::Diverged { .. } => "state diverged",
+        }
+    }
+
+    fn cause(&self) -> Option<&Error> {
+        match *self {
+            ReplayError::Load(ref err) => Some(err),
+            ReplayError::Diverged { .. } => None,
+        }
+    }
+}
+
+impl From<LoadError> for ReplayError {
+    fn from(err: LoadError) -> Self {
+        ReplayError::Load(err)
+    }
+}
+
+impl From<io::Error> for ReplayError {
+    fn from(err: io::Error) -> Self {
+        ReplayError::Load(LoadError::Io(err))
+    }
+}
+
+/// Replay a log into a world and scheduler, checking the state hash after each
+/// tick. Returns the number of ticks replayed.
+pub fn replay<S: Set, I: Input<S>, R: Read>(reader: &mut R, world: &mut World<S>, scheduler: &mut Scheduler<S>)
+-> Result<usize, ReplayError> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != LOG_MAGIC { return Err(LoadError::BadMagic.into()) }
+
+    let mut dec = Decoder { reader: reader, depth: 0 };
+    let version = dec.u32()?;
+    if version > VERSION { return Err(LoadError::UnsupportedVersion(version).into()) }
+
+    let mut tick = 0;
+    loop {
+        // the log may end between any two ticks.
+        let mut tag = [0];
+        if dec.reader.read(&mut tag)? == 0 { return Ok(tick) }
+        if tag[0] != TICK { return Err(LoadError::Corrupt("tick tag").into()) }
+
+        let mut inputs = Vec::new();
+        for _ in 0..dec.len()? {
+            let value = dec.value()?;
+            inputs.push(I::from_value(value).map_err(|err| LoadError::Reflect(I::type_name(), err))?);
+        }
+
+        let mut bytes = [0; 8];
+        dec.reader.read_exact(&mut bytes)?;
+        let expected = bytes.iter().rev().fold(0, |hash, &b| (hash << 8) | b as u64);
+
+        let actual = step(world, scheduler, &inputs);
+        if actual != expected {
+            return Err(ReplayError::Diverged { tick: tick, expected: expected, actual: actual })
+        }
+
+        tick += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+
+    use super::*;
+    use super::super::super::reflect::tuple_elements;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(i64);
+
+    impl Component for Position {
+        type Storage = DefaultStorage<Self>;
+
+        fn hash_state(&self, state: &mut Hasher) -> bool {
+            state.write_i64(self.0);
+            true
+        }
+    }
+
+    // how far everything moves each tick.
+    #[derive(Hash)]
+    struct Speed(i64);
+
+    component_set! {
+        struct TestSet { Position }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    enum Command {
+        Spawn(i64),
+        SetSpeed(i64),
+    }
+
+    impl Reflect for Command {
+        fn type_name() -> &'static str { "Command" }
+
+        fn to_value(&self) -> Value {
+            match *self {
+                Command::Spawn(x) => Value::Tuple(vec![Value::UInt(0), x.to_value()]),
+                Command::SetSpeed(x) => Value::Tuple(vec![Value::UInt(1), x.to_value()]),
+            }
+        }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            let mut elements = tuple_elements(value, 2)?;
+            let kind: u32 = Reflect::from_value(elements.next().unwrap())?;
+            let x = Reflect::from_value(elements.next().unwrap())?;
+            Ok(if kind == 0 { Command::Spawn(x) } else { Command::SetSpeed(x) })
+        }
+    }
+
+    impl Input<TestSet> for Command {
+        fn apply(&self, world: &mut World<TestSet>) {
+            match *self {
+                Command::Spawn(x) => { world.build_entity().with(Position(x)).spawn(); }
+                Command::SetSpeed(x) => { world.resources_mut().insert_hashed(Speed(x)); }
+            }
+        }
+    }
+
+    struct Movement;
+
+    impl System for Movement {
+        fn process<'a, S: 'a + Set>(&mut self, wh: WorldHandle<'a, S>) {
+            let speed = wh.resource::<Speed>().map_or(0, |s| s.0);
+            let mut positions = wh.data.lock_storage::<Position>();
+            for e in wh.entities.alive() {
+                let e = wh.entities.verify(e).unwrap();
+                if let Some(p) = positions.get_mut(e) { p.0 += speed; }
+            }
+        }
+    }
+
+    fn setup() -> (World<TestSet>, Scheduler<TestSet>) {
+        (World::new(TestSet::new()), Scheduler::new().with("movement", Movement))
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let ticks = vec![
+            vec![Command::Spawn(0), Command::SetSpeed(2)],
+            vec![],
+            vec![Command::Spawn(10)],
+            vec![Command::SetSpeed(-1)],
+        ];
+
+        let (mut world, mut scheduler) = setup();
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        for inputs in &ticks {
+            recorder.tick(&mut world, &mut scheduler, inputs).unwrap();
+        }
+        assert_eq!(recorder.ticks(), 4);
+        let log = recorder.finish().unwrap();
+
+        let (mut world, mut scheduler) = setup();
+        assert_eq!(replay::<_, Command, _>(&mut &log[..], &mut world, &mut scheduler).unwrap(), 4);
+
+        // a world which starts out differently diverges on the first tick.
+        let (mut world, mut scheduler) = setup();
+        world.build_entity().with(Position(5)).spawn();
+        match replay::<_, Command, _>(&mut &log[..], &mut world, &mut scheduler) {
+            Err(ReplayError::Diverged { tick: 0, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // the speed is hashed, so a different one diverges even with nothing to move.
+        let (mut world, mut scheduler) = setup();
+        world.resources_mut().insert_hashed(Speed(1));
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        recorder.tick::<_, Command>(&mut world, &mut scheduler, &[]).unwrap();
+        let speed_log = recorder.finish().unwrap();
+
+        let (mut world, mut scheduler) = setup();
+        world.resources_mut().insert_hashed(Speed(3));
+        match replay::<_, Command, _>(&mut &speed_log[..], &mut world, &mut scheduler) {
+            Err(ReplayError::Diverged { tick: 0, .. }) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // a log cut short within a tick is an error, but not between ticks.
+        let (mut world, mut scheduler) = setup();
+        assert!(replay::<_, Command, _>(&mut &log[..log.len() - 1], &mut world, &mut scheduler).is_err());
+
+        let (mut world, mut scheduler) = setup();
+        let mut recorder = Recorder::new(Vec::new()).unwrap();
+        for inputs in &ticks[..2] {
+            recorder.tick(&mut world, &mut scheduler, inputs).unwrap();
+        }
+        let boundary = recorder.finish().unwrap().len();
+
+        let (mut world, mut scheduler) = setup();
+        assert_eq!(replay::<_, Command, _>(&mut &log[..boundary], &mut world, &mut scheduler).unwrap(), 2);
+    }
+}
@@ -0,0 +1,712 @@
+//! Replicating world state from a server to clients.
+//!
+//! The components in a `Registry` are replicated for every entity which has
+//! at least one of them. A `ReplicationServer` remembers what it last sent each
+//! client, and sends a packet with only the differences whenever something changed.
+//! A `ReplicationClient` applies packets to its own world, spawning a local entity
+//! for each server entity and remapping the handles within component data.
+//!
+//! Packets go through a `Transport`, which must deliver them reliably and in
+//! order. The client and server registries must list the same components in
+//! the same order.
+//!
+//! By default every client is sent every entity. Giving a client an `Interest`
+//! limits it to the entities relevant to it, such as those near its player with
+//! `RadiusInterest`. An entity entering a client's scope is sent whole, as if it
+//! had just spawned, and an entity leaving it is despawned on the client. The
+//! server reports these as `ScopeEvent`s.
+//!
+//! The server keeps the reflected data of each storage, and only reflects it
+//! again once the storage has been borrowed mutably, so it should be given the
+//! same registry and world on every update.
+//!
+//! # Packet format
+//!
+//! Integers and values are encoded as in saved worlds.
+//!
+//! ```text
+//! version    varint
+//! updates    count, then per entity: server entity, count, then per component:
+//!            registry index, then 0 if removed or 1 and the value if set
+//! despawns   count, then a server entity for each
+//! ```
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+
+use super::*;
+use super::super::storage::{Positioned2D, RadiusIndex};
+
+/// A channel for packets between a server and one client.
+pub trait Transport: Send {
+    /// Send a packet to the other end.
+    fn send(&mut self, packet: &[u8]) -> io::Result<()>;
+
+    /// Take the next packet received, if there is one.
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>>;
+}
+
+/// An in-memory transport, connected to another in the same process.
+pub struct Loopback {
+    incoming: Arc<Mutex<VecDeque<Vec<u8>>>>,
+    outgoing: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl Loopback {
+    /// Two transports connected to each other.
+    pub fn pair() -> (Loopback, Loopback) {
+        let a = Arc::new(Mutex::new(VecDeque::new()));
+        let b = Arc::new(Mutex::new(VecDeque::new()));
+
+        (Loopback { incoming: a.clone(), outgoing: b.clone() }, Loopback { incoming: b, outgoing: a })
+    }
+}
+
+impl Transport for Loopback {
+    fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.outgoing.lock().unwrap().push_back(packet.to_vec());
+        Ok(())
+    }
+
+    fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.incoming.lock().unwrap().pop_front())
+    }
+}
+
+/// Identifies a client of a `ReplicationServer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ClientId(usize);
+
+// the data of each replicated entity, by registry index.
+type State = HashMap<Entity, Vec<Option<Value>>>;
+
+// the reflected data of a storage, with the version it was taken at.
+type Cached = Option<(usize, Vec<(Entity, Value)>)>;
+
+// an entity's changed components, by registry index. `None` means removed.
+type Changes = Vec<(usize, Option<Value>)>;
+
+/// Decides which entities are relevant to a client.
+pub trait Interest<S: Set>: Send {
+    /// The entities the client should be sent this update.
+    fn relevant(&mut self, world: &World<S>) -> HashSet<Entity>;
+}
+
+/// The entities within a radius of a focus entity, such as a client's player,
+/// by their position in a spatial storage of `T`.
+///
+/// Nothing is relevant once the focus entity is dead or has no position.
+pub struct RadiusInterest<T> {
+    focus: Entity,
+    radius: f32,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> RadiusInterest<T> {
+    /// Interest in entities within `radius` of `focus`.
+    pub fn new(focus: Entity, radius: f32) -> Self {
+        RadiusInterest { focus: focus, radius: radius, _marker: PhantomData }
+    }
+
+    /// Change the focus entity.
+    pub fn set_focus(&mut self, focus: Entity) {
+        self.focus = focus;
+    }
+}
+
+impl<S: Set, T: Component + Positioned2D> Interest<S> for RadiusInterest<T> where T::Storage: RadiusIndex {
+    fn relevant(&mut self, world: &World<S>) -> HashSet<Entity> {
+        let focus = match world.entities.verify(self.focus) {
+            Some(focus) => focus,
+            None => return HashSet::new(),
+        };
+
+        let storage = world.data.lock_storage::<T>();
+        match storage.get(focus) {
+            Some(data) => storage.within_radius(data.position(), self.radius).into_iter().collect(),
+            None => HashSet::new(),
+        }
+    }
+}
+
+/// A change in which entities a client is sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScopeEvent {
+    /// The client was sent the entity for the first time since it was last in scope.
+    Entered(ClientId, Entity),
+    /// The entity was despawned on the client.
+    Left(ClientId, Entity),
+}
+
+/// The outcome of `ReplicationServer::update`.
+#[derive(Debug)]
+pub struct Update {
+    /// The entities which entered or left each client's scope.
+    pub events: Vec<ScopeEvent>,
+    /// The clients which couldn't be sent their changes, and why. They
+    /// catch up on the next update.
+    pub errors: Vec<(ClientId, io::Error)>,
+}
+
+struct Client<S: Set> {
+    id: ClientId,
+    transport: Box<Transport>,
+    interest: Option<Box<Interest<S>>>,
+    // the state as of the last packet sent.
+    sent: State,
+}
+
+/// Sends changes in a world's state to clients.
+pub struct ReplicationServer<S: Set> {
+    clients: Vec<Client<S>>,
+    next_id: usize,
+    // the reflected data of each registered storage.
+    cache: Vec<Cached>,
+}
+
+impl<S: Set> ReplicationServer<S> {
+    /// A server with no clients.
+    pub fn new() -> Self {
+        ReplicationServer { clients: Vec::new(), next_id: 0, cache: Vec::new() }
+    }
+
+    /// Add a client, which is sent every entity. It's sent
+    /// the whole state on the next update.
+    pub fn add_client<T: Transport + 'static>(&mut self, transport: T) -> ClientId {
+        let id = ClientId(self.next_id);
+        self.next_id += 1;
+        self.clients.push(Client { id: id, transport: Box::new(transport), interest: None, sent: State::new() });
+        id
+    }
+
+    /// Limit a client to the entities relevant to it, taking effect on the next update.
+    /// Returns false if there was no such client.
+    pub fn set_interest<I: Interest<S> + 'static>(&mut self, id: ClientId, interest: I) -> bool {
+        match self.clients.iter_mut().find(|client| client.id == id) {
+            Some(client) => {
+                client.interest = Some(Box::new(interest));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Send a client every entity again. Returns false if there was no such client.
+    pub fn clear_interest(&mut self, id: ClientId) -> bool {
+        match self.clients.iter_mut().find(|client| client.id == id) {
+            Some(client) => {
+                client.interest = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Stop sending to a client. Returns false if there was no such client.
+    pub fn remove_client(&mut self, id: ClientId) -> bool {
+        let before = self.clients.len();
+        self.clients.retain(|client| client.id != id);
+        self.clients.len() != before
+    }
+
+    /// Send each client the changes since its last packet, returning
+    /// the entities which entered or left each client's scope.
+    ///
+    /// Clients which are already up to date aren't sent anything. A client which
+    /// can't be sent its packet is reported in the errors, without events, and
+    /// the rest are still sent theirs.
+    pub fn update(&mut self, registry: &Registry<S>, world: &World<S>) -> Update {
+        let current = self.state(registry, world);
+        let mut events = Vec::new();
+        let mut errors = Vec::new();
+
+        for client in &mut self.clients {
+            let visible = match client.interest {
+                Some(ref mut interest) => {
+                    let relevant = interest.relevant(world);
+                    current.iter().filter(|&(e, _)| relevant.contains(e)).map(|(&e, v)| (e, v.clone())).collect()
+                }
+                None => current.clone(),
+            };
+
+            if let Some(packet) = delta(&client.sent, &visible) {
+                let id = client.id;
+                if let Err(err) = client.transport.send(&packet) {
+                    errors.push((id, err));
+                    continue;
+                }
+
+                events.extend(sorted(visible.keys().filter(|e| !client.sent.contains_key(e)))
+                    .into_iter().map(|e| ScopeEvent::Entered(id, e)));
+                events.extend(sorted(client.sent.keys().filter(|e| !visible.contains_key(e)))
+                    .into_iter().map(|e| ScopeEvent::Left(id, e)));
+                client.sent = visible;
+            }
+        }
+
+        Update { events: events, errors: errors }
+    }
+
+    // the data of every living entity, reflecting only storages which changed.
+    fn state(&mut self, registry: &Registry<S>, world: &World<S>) -> State {
+        if self.cache.len() != registry.entries.len() {
+            self.cache = registry.entries.iter().map(|_| None).collect();
+        }
+
+        let mut state = State::new();
+        let empty = vec![None; registry.entries.len()];
+
+        for (idx, entry) in registry.entries.iter().enumerate() {
+            let version = (entry.version)(&world.data);
+            let stale = match self.cache[idx] {
+                Some((v, _)) => v != version,
+                None => true,
+            };
+
+            if stale {
+                self.cache[idx] = Some((version, (entry.values)(&world.data, &world.entities)));
+            }
+
+            // entities may have died since without the storage changing.
+            let values = &self.cache[idx].as_ref().unwrap().1;
+            for &(e, ref value) in values.iter().filter(|&&(e, _)| world.entities.is_alive(e)) {
+                state.entry(e).or_insert_with(|| empty.clone())[idx] = Some(value.clone());
+            }
+        }
+
+        state
+    }
+}
+
+impl<S: Set> Default for ReplicationServer<S> {
+    fn default() -> Self {
+        ReplicationServer::new()
+    }
+}
+
+// entities in order of their handles, so packets don't depend on hash order.
+fn sorted<'a, I: Iterator<Item=&'a Entity>>(entities: I) -> Vec<Entity> {
+    let mut entities: Vec<Entity> = entities.cloned().collect();
+    entities.sort_by_key(|e| e.id);
+    entities
+}
+
+// the packet bringing a client from one state to another, if they differ.
+fn delta(from: &State, to: &State) -> Option<Vec<u8>> {
+    let mut updates = Vec::new();
+    for e in sorted(to.keys()) {
+        let before = from.get(&e);
+        let changes: Vec<(usize, &Option<Value>)> = to[&e].iter().enumerate()
+            .filter(|&(idx, value)| match before {
+                Some(before) => before[idx] != *value,
+                None => value.is_some(),
+            })
+            .collect();
+
+        if !changes.is_empty() { updates.push((e, changes)) }
+    }
+
+    let despawns = sorted(from.keys().filter(|e| !to.contains_key(e)));
+
+    if updates.is_empty() && despawns.is_empty() { return None }
+
+    let mut enc = Encoder { out: Vec::new() };
+    enc.uint(VERSION as u64);
+
+    enc.uint(updates.len() as u64);
+    for (e, changes) in updates {
+        enc.entity(e);
+        enc.uint(changes.len() as u64);
+        for (idx, value) in changes {
+            enc.uint(idx as u64);
+            match *value {
+                Some(ref value) => {
+                    enc.out.push(1);
+                    enc.value(value);
+                }
+                None => enc.out.push(0),
+            }
+        }
+    }
+
+    enc.uint(despawns.len() as u64);
+    for e in despawns {
+        enc.entity(e);
+    }
+
+    Some(enc.out)
+}
+
+/// Applies packets from a `ReplicationServer` to a world.
+pub struct ReplicationClient {
+    // local entities for server entities.
+    map: HashMap<Entity, Entity>,
+    // what handles to unknown server entities are mapped to.
+    dead: Option<Entity>,
+}
+
+// a decoded packet.
+struct Packet {
+    updates: Vec<(Entity, Changes)>,
+    despawns: Vec<Entity>,
+}
+
+impl ReplicationClient {
+    /// A client which hasn't received anything.
+    pub fn new() -> Self {
+        ReplicationClient { map: HashMap::new(), dead: None }
+    }
+
+    /// The local entity for a server entity.
+    pub fn local(&self, server: Entity) -> Option<Entity> {
+        self.map.get(&server).cloned()
+    }
+
+    /// Apply every packet waiting in the transport, returning how many there were.
+    pub fn receive<S: Set, T: Transport + ?Sized>(&mut self, registry: &Registry<S>, world: &mut World<S>,
+                                                  transport: &mut T) -> Result<usize, LoadError> {
+        let mut count = 0;
+        while let Some(packet) = transport.receive()? {
+            self.apply(registry, world, &packet)?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Apply a packet to the world.
+    ///
+    /// Nothing is changed if the packet is malformed. Changes to local
+    /// entities which were despawned by the client are ignored.
+    pub fn apply<S: Set>(&mut self, registry: &Registry<S>, world: &mut World<S>, packet: &[u8])
+    -> Result<(), LoadError> {
+        let packet = decode(registry, packet)?;
+
+        for e in packet.despawns {
+            if let Some(local) = self.map.remove(&e) {
+                world.despawn(local);
+            }
+        }
+
+        // spawn every new entity first, so that handles to them can be mapped.
+        for &(e, _) in &packet.updates {
+            self.map.entry(e).or_insert_with(|| world.entities.next());
+        }
+
+        let dead = match self.dead {
+            Some(dead) => dead,
+            None => {
+                let dead = world.entities.next();
+                world.entities.destroy(dead);
+                self.dead = Some(dead);
+                dead
+            }
+        };
+
+        for (e, changes) in packet.updates {
+            let local = match world.entities.verify(self.map[&e]) {
+                Some(local) => local.entity(),
+                None => continue,
+            };
+
+            for (idx, value) in changes {
+                let entry = &registry.entries[idx];
+                let verified = world.entities.verify(local).unwrap();
+                match value {
+                    Some(mut value) => {
+                        let map = &self.map;
+                        value.map_entities(&mut |e| *map.get(&e).unwrap_or(&dead));
+                        (entry.insert)(&mut world.data, verified, value)
+                            .map_err(|err| LoadError::Reflect(entry.name, err))?;
+                    }
+                    None => (entry.remove)(&mut world.data, verified),
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Default for ReplicationClient {
+    fn default() -> Self {
+        ReplicationClient::new()
+    }
+}
+
+// read a packet and check its values, without changing anything.
+fn decode<S: Set>(registry: &Registry<S>, mut packet: &[u8]) -> Result<Packet, LoadError> {
+    let mut dec = Decoder { reader: &mut packet, depth: 0 };
+    let version = dec.u32()?;
+    if version > VERSION { return Err(LoadError::UnsupportedVersion(version)) }
+
+    let mut updates = Vec::new();
+    for _ in 0..dec.len()? {
+        let e = dec.entity()?;
+        let mut changes = Vec::new();
+        for _ in 0..dec.len()? {
+            let idx = dec.len()?;
+            let entry = registry.entries.get(idx).ok_or(LoadError::Corrupt("component index"))?;
+
+            let value = match dec.byte()? {
+                0 => None,
+                1 => {
+                    let value = dec.value()?;
+                    (entry.check)(value.clone()).map_err(|err| LoadError::Reflect(entry.name, err))?;
+                    Some(value)
+                }
+                _ => return Err(LoadError::Corrupt("component change")),
+            };
+
+            changes.push((idx, value));
+        }
+
+        updates.push((e, changes));
+    }
+
+    let mut despawns = Vec::new();
+    for _ in 0..dec.len()? {
+        despawns.push(dec.entity()?);
+    }
+
+    if !packet.is_empty() { return Err(LoadError::Corrupt("trailing data")) }
+    Ok(Packet { updates: updates, despawns: despawns })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::hierarchy::Parent;
+    use super::super::super::reflect::{tuple_elements, Reflect};
+    use super::super::super::storage::QuadtreeStorage;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Health(u32);
+
+    impl Component for Health { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Health {}
+
+    impl Reflect for Health {
+        fn type_name() -> &'static str { "Health" }
+        fn to_value(&self) -> Value { Value::Tuple(vec![self.0.to_value()]) }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            Ok(Health(Reflect::from_value(tuple_elements(value, 1)?.next().unwrap())?))
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Pos(f32, f32);
+
+    impl Component for Pos { type Storage = QuadtreeStorage<Self>; }
+    impl SerializeComponent for Pos {}
+
+    impl Positioned2D for Pos {
+        fn position(&self) -> (f32, f32) { (self.0, self.1) }
+    }
+
+    impl Reflect for Pos {
+        fn type_name() -> &'static str { "Pos" }
+        fn to_value(&self) -> Value { Value::Tuple(vec![self.0.to_value(), self.1.to_value()]) }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            let mut elements = tuple_elements(value, 2)?;
+            Ok(Pos(Reflect::from_value(elements.next().unwrap())?, Reflect::from_value(elements.next().unwrap())?))
+        }
+    }
+
+    // counts how often it's reflected.
+    static SCORES_REFLECTED: AtomicUsize = AtomicUsize::new(0);
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Score(u32);
+
+    impl Component for Score { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Score {}
+
+    impl Reflect for Score {
+        fn type_name() -> &'static str { "Score" }
+
+        fn to_value(&self) -> Value {
+            SCORES_REFLECTED.fetch_add(1, Ordering::SeqCst);
+            Value::Tuple(vec![self.0.to_value()])
+        }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            Ok(Score(Reflect::from_value(tuple_elements(value, 1)?.next().unwrap())?))
+        }
+    }
+
+    // fails to send the first packets.
+    struct Flaky(usize, Loopback);
+
+    impl Transport for Flaky {
+        fn send(&mut self, packet: &[u8]) -> io::Result<()> {
+            if self.0 > 0 {
+                self.0 -= 1;
+                return Err(io::Error::other("flaky"))
+            }
+            self.1.send(packet)
+        }
+
+        fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+            self.1.receive()
+        }
+    }
+
+    component_set! {
+        struct TestSet { Health, Parent, Pos, Score }
+    }
+
+    fn registry() -> Registry<TestSet> {
+        Registry::new().register::<Health>().register::<Parent>().register::<Pos>()
+            .register::<Score>()
+    }
+
+    fn health(world: &World<TestSet>, e: Entity) -> Option<u32> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Health>().get(e).map(|h| h.0)
+    }
+
+    #[test]
+    fn replicate_over_loopback() {
+        let registry = registry();
+        let mut server_world = World::new(TestSet::new());
+        let mut client_world = World::new(TestSet::new());
+        // so that server and client handles differ.
+        client_world.build_entity().spawn();
+
+        let (server_end, mut client_end) = Loopback::pair();
+        let mut server = ReplicationServer::new();
+        server.add_client(server_end);
+        let mut client = ReplicationClient::new();
+
+        let a = server_world.build_entity().with(Health(10)).spawn();
+        let b = server_world.build_entity().with(Health(5)).spawn();
+        server_world.set_parent(b, a).unwrap();
+        assert_eq!(server.update(&registry, &server_world).events.len(), 2);
+        assert_eq!(client.receive(&registry, &mut client_world, &mut client_end).unwrap(), 1);
+
+        let (la, lb) = (client.local(a).unwrap(), client.local(b).unwrap());
+        assert!(la != a);
+        assert_eq!(health(&client_world, la), Some(10));
+        assert_eq!(client_world.parent(lb), Some(la));
+
+        // nothing changed, so nothing is sent.
+        assert_eq!(server.update(&registry, &server_world).events, vec![]);
+        assert_eq!(client.receive(&registry, &mut client_world, &mut client_end).unwrap(), 0);
+
+        {
+            let b = server_world.entities.verify(b).unwrap();
+            server_world.data.get_storage_mut::<Health>().set(b, Health(4));
+        }
+        server_world.despawn(a);
+        server.update(&registry, &server_world);
+        client.receive(&registry, &mut client_world, &mut client_end).unwrap();
+
+        assert!(!client_world.entities.is_alive(la));
+        assert_eq!(health(&client_world, lb), Some(4));
+        assert_eq!(client.local(a), None);
+
+        // bad packets change nothing.
+        let mut bad = ReplicationClient::new();
+        let mut other = World::new(TestSet::new());
+        match bad.apply(&registry, &mut other, &[1, 1, 0, 1, 0, 1, 0, 0]) {
+            Err(LoadError::Reflect("Health", _)) => {}
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(other.entities.alive().is_empty());
+        assert!(bad.apply(&registry, &mut other, &[1, 1, 0, 1, 9, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn radius_interest() {
+        let registry = registry();
+        let mut server_world = World::new(TestSet::new());
+        let mut client_world = World::new(TestSet::new());
+
+        let (server_end, mut client_end) = Loopback::pair();
+        let mut server = ReplicationServer::new();
+        let id = server.add_client(server_end);
+        let mut client = ReplicationClient::new();
+
+        let player = server_world.build_entity().with(Pos(0.0, 0.0)).spawn();
+        let near = server_world.build_entity().with(Pos(3.0, 4.0)).with(Health(1)).spawn();
+        let far = server_world.build_entity().with(Pos(50.0, 0.0)).spawn();
+        assert!(server.set_interest(id, RadiusInterest::<Pos>::new(player, 10.0)));
+
+        let events = server.update(&registry, &server_world).events;
+        assert_eq!(events, vec![ScopeEvent::Entered(id, player), ScopeEvent::Entered(id, near)]);
+        client.receive(&registry, &mut client_world, &mut client_end).unwrap();
+        assert!(client.local(near).is_some());
+        assert_eq!(client.local(far), None);
+
+        // the far entity moves in and the near one moves out.
+        for &(e, pos) in &[(far, Pos(0.0, 9.0)), (near, Pos(20.0, 0.0))] {
+            let e = server_world.entities.verify(e).unwrap();
+            server_world.data.get_storage_mut::<Pos>().set(e, pos);
+        }
+
+        let events = server.update(&registry, &server_world).events;
+        assert_eq!(events, vec![ScopeEvent::Entered(id, far), ScopeEvent::Left(id, near)]);
+        let local_near = client.local(near).unwrap();
+        client.receive(&registry, &mut client_world, &mut client_end).unwrap();
+
+        assert!(!client_world.entities.is_alive(local_near));
+        let local_far = client.local(far).unwrap();
+        let local_far = client_world.entities.verify(local_far).unwrap();
+        assert_eq!(client_world.data.lock_storage::<Pos>().get(local_far), Some(&Pos(0.0, 9.0)));
+
+        // without interest, everything is sent again.
+        server.clear_interest(id);
+        assert_eq!(server.update(&registry, &server_world).events, vec![ScopeEvent::Entered(id, near)]);
+    }
+
+    #[test]
+    fn unchanged_storages_and_failed_sends() {
+        let registry = registry();
+        let mut world = World::new(TestSet::new());
+        let (flaky_end, mut flaky_client_end) = Loopback::pair();
+        let (server_end, mut client_end) = Loopback::pair();
+
+        let mut server = ReplicationServer::new();
+        let flaky = server.add_client(Flaky(1, flaky_end));
+        let steady = server.add_client(server_end);
+        let (mut flaky_client, mut client) = (ReplicationClient::new(), ReplicationClient::new());
+        let mut flaky_world = World::new(TestSet::new());
+        let mut client_world = World::new(TestSet::new());
+
+        let a = world.build_entity().with(Score(1)).spawn();
+        let b = world.build_entity().with(Score(2)).with(Health(3)).spawn();
+
+        // the other client still gets its changes and events.
+        let update = server.update(&registry, &world);
+        assert_eq!(update.events, vec![ScopeEvent::Entered(steady, a), ScopeEvent::Entered(steady, b)]);
+        assert_eq!(update.errors.len(), 1);
+        assert_eq!(update.errors[0].0, flaky);
+        assert_eq!(flaky_client.receive(&registry, &mut flaky_world, &mut flaky_client_end).unwrap(), 0);
+        assert_eq!(client.receive(&registry, &mut client_world, &mut client_end).unwrap(), 1);
+        let reflected = SCORES_REFLECTED.load(Ordering::SeqCst);
+
+        // and the failed one catches up next time, without scores being reflected again.
+        {
+            let b = world.entities.verify(b).unwrap();
+            world.data.get_storage_mut::<Health>().set(b, Health(4));
+            assert_eq!(world.data.lock_storage::<Score>().get(b), Some(&Score(2)));
+        }
+        world.entities.destroy(a);
+
+        let update = server.update(&registry, &world);
+        assert!(update.errors.is_empty());
+        assert_eq!(update.events, vec![ScopeEvent::Entered(flaky, b), ScopeEvent::Left(steady, a)]);
+        assert_eq!(SCORES_REFLECTED.load(Ordering::SeqCst), reflected);
+
+        flaky_client.receive(&registry, &mut flaky_world, &mut flaky_client_end).unwrap();
+        let local = flaky_world.entities.verify(flaky_client.local(b).unwrap()).unwrap();
+        assert_eq!(flaky_world.data.lock_storage::<Health>().get(local), Some(&Health(4)));
+        assert_eq!(flaky_client.local(a), None);
+    }
+}
@@ -0,0 +1,1003 @@
+//! A human-readable text format for scenes.
+//!
+//! Scenes list entities by label, each with its components. Component data is
+//! written the same way as its reflected `Value`, and entities are referred to
+//! by `@label`. `@_` refers to an entity which isn't alive.
+//!
+//! ```text
+//! // the player, and the sword they're holding.
+//! entity player {
+//!     Name("Snork"),
+//!     Position { x: 1.5, y: -2.0 },
+//! }
+//!
+//! entity sword {
+//!     Parent(@player),
+//!     Stats { damage: 12, enchantment: Some("fire"), tags: ["sharp", "shiny"] },
+//!     Score = 300,
+//! }
+//! ```
+//!
+//! A component is its name followed by a struct in braces, a tuple in parentheses,
+//! `=` and any other value, or nothing for unit components. Values are:
+//!
+//! * `()`, `true` and `false`
+//! * integers like `3` and `-3`, and floats like `1.0`, `-2e3`, `inf`, and `NaN`
+//! * strings in double quotes, with the escapes `\"`, `\\`, `\n`, `\r`, `\t`, and `\u{..}`
+//! * entities like `@player`
+//! * `None` and `Some(value)`
+//! * lists like `[a, b]`, tuples like `(a, b)`, and structs like `{ x: a, y: b }`,
+//!   where tuples and structs may be preceded by a type name for readability.
+//!   The empty tuple is `(,)`, since `()` is the unit value
+//! * type names on their own, which are unit values
+//!
+//! Line comments start with `//`.
+//!
+//! # Prefabs
+//!
+//! `prefab` blocks declare prefabs, which entities and later prefabs can
+//! inherit from by naming them after a colon. Struct components given in
+//! a derived block only override the fields they list. `child` blocks
+//! add child entities.
+//!
+//! ```text
+//! prefab goblin {
+//!     Stats { damage: 2, tags: ["green"] },
+//!     child { Name("club") },
+//! }
+//!
+//! prefab goblin_chief: goblin {
+//!     Stats { damage: 5 },
+//! }
+//!
+//! entity boss: goblin_chief {
+//!     Name("Grusk"),
+//! }
+//! ```
+//!
+//! Entities can't be referred to from within prefabs.
+
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{self, Write};
+
+use super::*;
+use super::prefab::Prefab;
+
+/// An error in a scene, with the position it occurred at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SceneError {
+    /// The line, counting from 1.
+    pub line: usize,
+    /// The column, counting from 1.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for SceneError {
+    fn description(&self) -> &str {
+        "error in scene"
+    }
+}
+
+impl<S: Set> Registry<S> {
+    /// Spawn the entities of a scene into a world, returning the entity
+    /// spawned for each label.
+    ///
+    /// Nothing is spawned if the scene has an error.
+    pub fn load_scene(&self, world: &mut World<S>, text: &str) -> Result<HashMap<String, Entity>, SceneError> {
+        let scene = Parser::new(text, self)?.scene(true)?;
+
+        // labels only appear as entity ids within the parsed values until now.
+        let spawned: Vec<Entity> = scene.labels.iter().map(|_| world.entities.next()).collect();
+        let dead = world.entities.next();
+        world.entities.destroy(dead);
+
+        let mut created = spawned.clone();
+        for entity in &scene.entities {
+            let mut map = |e: Entity| match scene.labels[e.id() as usize].as_ref() {
+                "_" => dead,
+                _ => spawned[e.id() as usize],
+            };
+
+            let result = self.insert_prefab(world, spawned[entity.label], &entity.prefab, &mut map, &mut created);
+            if let Err((path, err)) = result {
+                for &e in &created {
+                    world.despawn(e);
+                }
+
+                return Err(entity.spans.error(&path, err.to_string()))
+            }
+        }
+
+        let mut labels = HashMap::new();
+        for entity in scene.entities {
+            labels.insert(scene.labels[entity.label].clone(), spawned[entity.label]);
+        }
+
+        // entities which were only referred to never get any data.
+        for (label, &e) in scene.labels.iter().zip(&spawned) {
+            if !labels.contains_key(label) { world.entities.destroy(e); }
+        }
+
+        Ok(labels)
+    }
+
+    /// Read the prefabs declared in a scene, by name.
+    ///
+    /// The scene may only contain `prefab` blocks.
+    pub fn load_prefabs(&self, text: &str) -> Result<HashMap<String, Prefab>, SceneError> {
+        let scene = Parser::new(text, self)?.scene(false)?;
+        Ok(scene.prefabs)
+    }
+
+    /// Write every living entity in a world along with its registered components.
+    ///
+    /// Entities are labelled by their ids, like `e12`.
+    pub fn write_scene(&self, world: &World<S>) -> String {
+        let mut components: HashMap<Entity, Vec<(&'static str, Value)>> = HashMap::new();
+        for entry in &self.entries {
+            for (e, value) in (entry.values)(&world.data, &world.entities) {
+                components.entry(e).or_default().push((entry.name, value));
+            }
+        }
+
+        let mut out = String::new();
+        for e in world.entities.alive() {
+            if !out.is_empty() { out.push('\n'); }
+            out.push_str(&format!("entity e{} {{\n", e.id()));
+
+            for (name, value) in components.remove(&e).unwrap_or_default() {
+                let mut writer = Writer { out: &mut out, entities: &world.entities };
+                writer.component(name, &value);
+            }
+
+            out.push_str("}\n");
+        }
+
+        out
+    }
+}
+
+struct Writer<'a> {
+    out: &'a mut String,
+    entities: &'a EntityManager,
+}
+
+impl<'a> Writer<'a> {
+    fn component(&mut self, name: &str, value: &Value) {
+        self.out.push_str("    ");
+        self.out.push_str(name);
+
+        match *value {
+            Value::Unit => {}
+            Value::Tuple(_) => self.value(value),
+            Value::Struct(_) => {
+                self.out.push(' ');
+                self.value(value);
+            }
+            _ => {
+                self.out.push_str(" = ");
+                self.value(value);
+            }
+        }
+
+        self.out.push_str(",\n");
+    }
+
+    fn value(&mut self, value: &Value) {
+        match *value {
+            Value::Unit => self.out.push_str("()"),
+            Value::Bool(b) => self.out.push_str(if b { "true" } else { "false" }),
+            Value::Int(n) => write!(self.out, "{}", n).unwrap(),
+            Value::UInt(n) => write!(self.out, "{}", n).unwrap(),
+            Value::Float(x) => write!(self.out, "{:?}", x).unwrap(),
+            Value::Str(ref s) => {
+                self.out.push('"');
+                for c in s.chars() {
+                    match c {
+                        '"' => self.out.push_str("\\\""),
+                        '\\' => self.out.push_str("\\\\"),
+                        '\n' => self.out.push_str("\\n"),
+                        '\r' => self.out.push_str("\\r"),
+                        '\t' => self.out.push_str("\\t"),
+                        c if c.is_control() => write!(self.out, "\\u{{{:x}}}", c as u32).unwrap(),
+                        c => self.out.push(c),
+                    }
+                }
+                self.out.push('"');
+            }
+            Value::Entity(e) => {
+                if self.entities.is_alive(e) {
+                    write!(self.out, "@e{}", e.id()).unwrap();
+                } else {
+                    self.out.push_str("@_");
+                }
+            }
+            Value::Option(None) => self.out.push_str("None"),
+            Value::Option(Some(ref v)) => {
+                self.out.push_str("Some(");
+                self.value(v);
+                self.out.push(')');
+            }
+            Value::List(ref vs) => {
+                self.out.push('[');
+                self.values(vs);
+                self.out.push(']');
+            }
+            // `()` would be read back as a unit value.
+            Value::Tuple(ref vs) if vs.is_empty() => self.out.push_str("(,)"),
+            Value::Tuple(ref vs) => {
+                self.out.push('(');
+                self.values(vs);
+                self.out.push(')');
+            }
+            Value::Struct(ref fields) => {
+                self.out.push_str(if fields.is_empty() { "{" } else { "{ " });
+                for (i, (name, v)) in fields.iter().enumerate() {
+                    if i > 0 { self.out.push_str(", "); }
+                    self.out.push_str(name);
+                    self.out.push_str(": ");
+                    self.value(v);
+                }
+                self.out.push_str(if fields.is_empty() { "}" } else { " }" });
+            }
+        }
+    }
+
+    fn values(&mut self, vs: &[Value]) {
+        for (i, v) in vs.iter().enumerate() {
+            if i > 0 { self.out.push_str(", "); }
+            self.value(v);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    UInt(u64),
+    Float(f64),
+    Str(String),
+    Punct(char),
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Token::Ident(ref s) => write!(f, "`{}`", s),
+            Token::Int(n) => write!(f, "`{}`", n),
+            Token::UInt(n) => write!(f, "`{}`", n),
+            Token::Float(x) => write!(f, "`{:?}`", x),
+            Token::Str(ref s) => write!(f, "{:?}", s),
+            Token::Punct(c) => write!(f, "`{}`", c),
+            Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+// a token along with its line and column.
+type Spanned = (Token, usize, usize);
+
+fn tokenize(text: &str) -> Result<Vec<Spanned>, SceneError> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    let (mut line, mut column) = (1, 1);
+
+    macro_rules! bump {
+        () => {{
+            let c = chars.next();
+            if c == Some('\n') {
+                line += 1;
+                column = 1;
+            } else if c.is_some() {
+                column += 1;
+            }
+            c
+        }}
+    }
+
+    loop {
+        let (start_line, start_column) = (line, column);
+        let err = |message: String| SceneError { line: start_line, column: start_column, message: message };
+
+        let c = match chars.peek() {
+            Some(&c) => c,
+            None => break,
+        };
+
+        let token = match c {
+            c if c.is_whitespace() => {
+                bump!();
+                continue
+            }
+            '/' => {
+                bump!();
+                if chars.peek() != Some(&'/') { return Err(err("expected `//` to start a comment".to_owned())) }
+                while chars.peek().is_some_and(|&c| c != '\n') { bump!(); }
+                continue
+            }
+            '{' | '}' | '(' | ')' | '[' | ']' | ',' | ':' | '=' | '@' => {
+                bump!();
+                Token::Punct(c)
+            }
+            '"' => {
+                bump!();
+                let mut s = String::new();
+                loop {
+                    match bump!() {
+                        Some('"') => break,
+                        Some('\\') => match bump!() {
+                            Some('"') => s.push('"'),
+                            Some('\\') => s.push('\\'),
+                            Some('n') => s.push('\n'),
+                            Some('r') => s.push('\r'),
+                            Some('t') => s.push('\t'),
+                            Some('u') => {
+                                let mut hex = String::new();
+                                if bump!() != Some('{') { return Err(err("expected `{` in unicode escape".to_owned())) }
+                                loop {
+                                    match bump!() {
+                                        Some('}') => break,
+                                        Some(c) => hex.push(c),
+                                        None => return Err(err("unterminated string".to_owned())),
+                                    }
+                                }
+
+                                match u32::from_str_radix(&hex, 16).ok().and_then(::std::char::from_u32) {
+                                    Some(c) => s.push(c),
+                                    None => return Err(err(format!("invalid unicode escape `{}`", hex))),
+                                }
+                            }
+                            Some(c) => return Err(err(format!("unknown escape `\\{}`", c))),
+                            None => return Err(err("unterminated string".to_owned())),
+                        },
+                        Some(c) => s.push(c),
+                        None => return Err(err("unterminated string".to_owned())),
+                    }
+                }
+
+                Token::Str(s)
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut s = String::new();
+                s.push(bump!().unwrap());
+
+                let mut float = false;
+                while let Some(&c) = chars.peek() {
+                    // signs only continue a number in an exponent.
+                    let sign = (c == '+' || c == '-') && (s.ends_with('e') || s.ends_with('E'));
+                    if !(c.is_alphanumeric() || c == '_' || c == '.' || sign) { break }
+
+                    float |= c == '.' || c == 'e' || c == 'E' || c == 'i';
+                    s.push(c);
+                    bump!();
+                }
+
+                let token = if s == "-inf" {
+                    Some(Token::Float(-f64::INFINITY))
+                } else if float {
+                    s.parse().ok().map(Token::Float)
+                } else if s.starts_with('-') {
+                    s.parse().ok().map(Token::Int)
+                } else {
+                    s.parse().ok().map(Token::UInt)
+                };
+
+                match token {
+                    Some(token) => token,
+                    None => return Err(err(format!("invalid number `{}`", s))),
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut s = String::new();
+                while chars.peek().is_some_and(|&c| c.is_alphanumeric() || c == '_') {
+                    s.push(bump!().unwrap());
+                }
+
+                match s.as_ref() {
+                    "inf" => Token::Float(f64::INFINITY),
+                    "NaN" => Token::Float(f64::NAN),
+                    _ => Token::Ident(s),
+                }
+            }
+            c => return Err(err(format!("unexpected character `{}`", c))),
+        };
+
+        tokens.push((token, start_line, start_column));
+    }
+
+    tokens.push((Token::Eof, line, column));
+    Ok(tokens)
+}
+
+struct Scene {
+    // every label declared or referred to. entity values hold indices into this.
+    labels: Vec<String>,
+    entities: Vec<SceneEntity>,
+    prefabs: HashMap<String, Prefab>,
+}
+
+struct SceneEntity {
+    label: usize,
+    prefab: Prefab,
+    spans: Spans,
+}
+
+struct SceneComponent {
+    entry: usize,
+    value: Value,
+    line: usize,
+    column: usize,
+}
+
+// where each component of a prefab was last set, mirroring its structure.
+#[derive(Clone, Default)]
+struct Spans {
+    components: Vec<(usize, usize)>,
+    children: Vec<Spans>,
+}
+
+impl Spans {
+    // an error at the component with the given path through the children.
+    fn error(&self, path: &[usize], message: String) -> SceneError {
+        if path.len() > 1 { return self.children[path[0]].error(&path[1..], message) }
+
+        let (line, column) = self.components[path[0]];
+        SceneError { line: line, column: column, message: message }
+    }
+}
+
+struct Parser<'a, S: 'a + Set> {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    registry: &'a Registry<S>,
+    labels: Vec<String>,
+    label_indices: HashMap<String, usize>,
+    // where each label was first referred to, for those never declared.
+    references: HashMap<usize, (usize, usize)>,
+    prefabs: HashMap<String, (Prefab, Spans)>,
+    // whether a prefab is being parsed, where entities can't be referred to.
+    in_prefab: bool,
+    // how many values are being parsed, each within the last.
+    depth: usize,
+}
+
+impl<'a, S: 'a + Set> Parser<'a, S> {
+    fn new(text: &str, registry: &'a Registry<S>) -> Result<Self, SceneError> {
+        Ok(Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            registry: registry,
+            labels: Vec::new(),
+            label_indices: HashMap::new(),
+            references: HashMap::new(),
+            prefabs: HashMap::new(),
+            in_prefab: false,
+            depth: 0,
+        })
+    }
+
+    // the token at the current position. the last token is always `Eof`.
+    fn current(&self) -> &Spanned {
+        &self.tokens[::std::cmp::min(self.pos, self.tokens.len() - 1)]
+    }
+
+    fn peek(&self) -> &Token {
+        &self.current().0
+    }
+
+    // the token after the current one.
+    fn peek_second(&self) -> &Token {
+        &self.tokens[::std::cmp::min(self.pos + 1, self.tokens.len() - 1)].0
+    }
+
+    fn next(&mut self) -> Spanned {
+        let token = self.current().clone();
+        self.pos += 1;
+        token
+    }
+
+    // an error at the next token.
+    fn error<T>(&self, message: String) -> Result<T, SceneError> {
+        let (_, line, column) = *self.current();
+        Err(SceneError { line: line, column: column, message: message })
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, SceneError> {
+        let message = format!("expected {}, found {}", expected, self.peek());
+        self.error(message)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if *self.peek() == Token::Punct(c) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), SceneError> {
+        if self.eat(c) { Ok(()) } else { self.unexpected(&format!("`{}`", c)) }
+    }
+
+    fn ident(&mut self, expected: &str) -> Result<(String, usize, usize), SceneError> {
+        match self.next() {
+            (Token::Ident(s), line, column) => Ok((s, line, column)),
+            _ => {
+                self.pos -= 1;
+                self.unexpected(expected)
+            }
+        }
+    }
+
+    fn label(&mut self, label: String) -> usize {
+        if let Some(&idx) = self.label_indices.get(&label) { return idx }
+
+        self.labels.push(label.clone());
+        self.label_indices.insert(label, self.labels.len() - 1);
+        self.labels.len() - 1
+    }
+
+    fn scene(mut self, allow_entities: bool) -> Result<Scene, SceneError> {
+        let mut entities = Vec::new();
+        let mut declared = HashSet::new();
+
+        while *self.peek() != Token::Eof {
+            let expected = if allow_entities { "`entity` or `prefab`" } else { "`prefab`" };
+            let (keyword, line, column) = self.ident(expected)?;
+            let is_prefab = match keyword.as_ref() {
+                "prefab" => true,
+                "entity" if allow_entities => false,
+                _ => {
+                    let message = format!("expected {}, found `{}`", expected, keyword);
+                    return Err(SceneError { line: line, column: column, message: message })
+                }
+            };
+
+            let (name, line, column) = self.ident(if is_prefab { "a prefab name" } else { "an entity label" })?;
+            let duplicate = if is_prefab {
+                self.prefabs.contains_key(&name)
+            } else {
+                name == "_" || !declared.insert(name.clone())
+            };
+
+            if duplicate {
+                let message = format!("{} `{}` is already declared", keyword, name);
+                return Err(SceneError { line: line, column: column, message: message })
+            }
+
+            // start from the base prefab, which must be declared before.
+            let (prefab, mut spans) = if self.eat(':') {
+                let (base, line, column) = self.ident("a prefab name")?;
+                match self.prefabs.get(&base) {
+                    Some((prefab, spans)) => (prefab.clone(), spans.clone()),
+                    None => {
+                        let message = format!("no prefab named `{}` declared before this", base);
+                        return Err(SceneError { line: line, column: column, message: message })
+                    }
+                }
+            } else {
+                (Prefab::new(), Spans::default())
+            };
+
+            // entities are spawned in the order their labels first appear.
+            let label = if is_prefab { None } else { Some(self.label(name.clone())) };
+
+            self.in_prefab = is_prefab;
+            let prefab = self.body(prefab, &mut spans)?;
+            if let Err((path, err)) = self.registry.check_prefab(&prefab) {
+                return Err(spans.error(&path, err.to_string()))
+            }
+
+            match label {
+                Some(label) => entities.push(SceneEntity { label: label, prefab: prefab, spans: spans }),
+                None => { self.prefabs.insert(name, (prefab, spans)); }
+            }
+        }
+
+        // every referenced entity must be declared somewhere.
+        for (idx, label) in self.labels.iter().enumerate() {
+            if label == "_" || declared.contains(label) { continue }
+
+            let (line, column) = self.references[&idx];
+            let message = format!("no entity labelled `{}` in the scene", label);
+            return Err(SceneError { line: line, column: column, message: message })
+        }
+
+        Ok(Scene {
+            labels: self.labels,
+            entities: entities,
+            prefabs: self.prefabs.into_iter().map(|(name, (prefab, _))| (name, prefab)).collect(),
+        })
+    }
+
+    // components and `child` blocks in braces, applied to the prefab as overrides.
+    fn body(&mut self, mut prefab: Prefab, spans: &mut Spans) -> Result<Prefab, SceneError> {
+        self.expect('{')?;
+        while !self.eat('}') {
+            let child = *self.peek() == Token::Ident("child".to_owned()) && *self.peek_second() == Token::Punct('{');
+            if child {
+                self.next();
+                let mut child_spans = Spans::default();
+                prefab = prefab.with_child(self.body(Prefab::new(), &mut child_spans)?);
+                spans.children.push(child_spans);
+            } else {
+                let component = self.component()?;
+                let name = self.registry.entries[component.entry].name;
+                prefab = prefab.with_value(name, component.value);
+
+                let idx = prefab.components().iter().position(|(n, _)| n == name).unwrap();
+                if idx == spans.components.len() {
+                    spans.components.push((component.line, component.column));
+                } else {
+                    spans.components[idx] = (component.line, component.column);
+                }
+            }
+
+            if !self.eat(',') {
+                self.expect('}')?;
+                break
+            }
+        }
+
+        Ok(prefab)
+    }
+
+    fn component(&mut self) -> Result<SceneComponent, SceneError> {
+        let (name, line, column) = self.ident("a component name")?;
+        let entry = match self.registry.entries.iter().position(|entry| entry.name == name) {
+            Some(entry) => entry,
+            None => {
+                let known: Vec<_> = self.registry.entries.iter().map(|entry| entry.name).collect();
+                let message = format!("unknown component `{}`; registered components are: {}", name, known.join(", "));
+                return Err(SceneError { line: line, column: column, message: message })
+            }
+        };
+
+        let value = match *self.peek() {
+            Token::Punct('{') | Token::Punct('(') => self.value()?,
+            Token::Punct('=') => {
+                self.next();
+                self.value()?
+            }
+            _ => Value::Unit,
+        };
+
+        Ok(SceneComponent { entry: entry, value: value, line: line, column: column })
+    }
+
+    fn value(&mut self) -> Result<Value, SceneError> {
+        if self.depth == MAX_DEPTH { return self.error("values nested too deeply".to_owned()) }
+
+        self.depth += 1;
+        let value = self.nested_value();
+        self.depth -= 1;
+        value
+    }
+
+    fn nested_value(&mut self) -> Result<Value, SceneError> {
+        let (token, line, column) = self.next();
+        Ok(match token {
+            Token::Int(n) => Value::Int(n),
+            Token::UInt(n) => Value::UInt(n),
+            Token::Float(x) => Value::Float(x),
+            Token::Str(s) => Value::Str(s),
+            Token::Punct('@') => {
+                if self.in_prefab {
+                    let message = "entities can't be referred to from prefabs".to_owned();
+                    return Err(SceneError { line: line, column: column, message: message })
+                }
+
+                let (label, _, _) = self.ident("an entity label")?;
+                let idx = self.label(label);
+                self.references.entry(idx).or_insert((line, column));
+                Value::Entity(Entity::new(0, idx as u32))
+            }
+            Token::Punct('[') => Value::List(self.values(']')?),
+            Token::Punct('(') => {
+                if self.eat(')') {
+                    Value::Unit
+                } else if self.eat(',') {
+                    self.expect(')')?;
+                    Value::Tuple(Vec::new())
+                } else {
+                    Value::Tuple(self.values(')')?)
+                }
+            }
+            Token::Punct('{') => self.fields()?,
+            Token::Ident(ident) => match ident.as_ref() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "None" => Value::Option(None),
+                "Some" => {
+                    self.expect('(')?;
+                    let value = self.value()?;
+                    self.expect(')')?;
+                    Value::Option(Some(Box::new(value)))
+                }
+                // type names are only for readability.
+                _ => match *self.peek() {
+                    Token::Punct('{') | Token::Punct('(') => self.value()?,
+                    _ => Value::Unit,
+                },
+            },
+            _ => {
+                self.pos -= 1;
+                return self.unexpected("a value")
+            }
+        })
+    }
+
+    // comma-separated values, up to the closing delimiter.
+    fn values(&mut self, close: char) -> Result<Vec<Value>, SceneError> {
+        let mut values = Vec::new();
+        while !self.eat(close) {
+            values.push(self.value()?);
+            if !self.eat(',') {
+                self.expect(close)?;
+                break
+            }
+        }
+
+        Ok(values)
+    }
+
+    // struct fields after the opening brace.
+    fn fields(&mut self) -> Result<Value, SceneError> {
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            let (name, _, _) = self.ident("a field name")?;
+            self.expect(':')?;
+            fields.push((name, self.value()?));
+            if !self.eat(',') {
+                self.expect('}')?;
+                break
+            }
+        }
+
+        Ok(Value::Struct(fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::hierarchy::Parent;
+    use super::super::super::reflect::{tuple_elements, Fields, Reflect};
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Stats {
+        name: String,
+        damage: f32,
+        tags: Vec<String>,
+    }
+
+    impl Component for Stats { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Stats {}
+
+    impl Reflect for Stats {
+        fn type_name() -> &'static str { "Stats" }
+
+        fn to_value(&self) -> Value {
+            Value::Struct(vec![
+                ("name".to_owned(), self.name.to_value()),
+                ("damage".to_owned(), self.damage.to_value()),
+                ("tags".to_owned(), self.tags.to_value()),
+            ])
+        }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            let mut fields = Fields::new(value)?;
+            let out = Stats {
+                name: fields.take("name")?,
+                damage: fields.take("damage")?,
+                tags: fields.take("tags")?,
+            };
+            fields.finish()?;
+            Ok(out)
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Score(i64);
+
+    impl Component for Score { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Score {}
+
+    impl Reflect for Score {
+        fn type_name() -> &'static str { "Score" }
+        fn to_value(&self) -> Value { Value::Tuple(vec![self.0.to_value()]) }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            Ok(Score(Reflect::from_value(tuple_elements(value, 1)?.next().unwrap())?))
+        }
+    }
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Marker();
+
+    impl Component for Marker { type Storage = DefaultStorage<Self>; }
+    impl SerializeComponent for Marker {}
+
+    impl Reflect for Marker {
+        fn type_name() -> &'static str { "Marker" }
+        fn to_value(&self) -> Value { Value::Tuple(Vec::new()) }
+
+        fn from_value(value: Value) -> Result<Self, ReflectError> {
+            tuple_elements(value, 0).map(|_| Marker())
+        }
+    }
+
+    component_set! {
+        struct TestSet { Stats, Score, Marker, Parent }
+    }
+
+    fn registry() -> Registry<TestSet> {
+        Registry::new().register::<Stats>().register::<Score>().register::<Marker>()
+            .register::<Parent>()
+    }
+
+    const SCENE: &str = r#"
+        // a sword held by the player.
+        entity player {
+            Score(-10),
+            Stats { name: "Snork \"the\" Brave", damage: 2, tags: [] },
+        }
+
+        entity sword {
+            Stats { name: "sword", damage: 1.5e1, tags: ["sharp", "shiny"], },
+        }
+    "#;
+
+    fn stats(world: &World<TestSet>, e: Entity) -> Stats {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Stats>().get(e).unwrap().clone()
+    }
+
+    #[test]
+    fn load_and_write() {
+        let registry = registry();
+        let mut world = World::new(TestSet::new());
+        let labels = registry.load_scene(&mut world, SCENE).unwrap();
+        let (player, sword) = (labels["player"], labels["sword"]);
+
+        assert_eq!(stats(&world, player).name, "Snork \"the\" Brave");
+        assert_eq!(stats(&world, sword).damage, 15.0);
+        assert_eq!(stats(&world, sword).tags, vec!["sharp".to_owned(), "shiny".to_owned()]);
+        world.set_parent(sword, player).unwrap();
+
+        // writing and loading again gives the same world.
+        let text = registry.write_scene(&world);
+        let mut copy = World::new(TestSet::new());
+        let labels = registry.load_scene(&mut copy, &text).unwrap();
+        let player_copy = labels[&format!("e{}", player.id())];
+        let sword_copy = labels[&format!("e{}", sword.id())];
+
+        assert_eq!(stats(&copy, sword_copy), stats(&world, sword));
+        assert_eq!(copy.children(player_copy), vec![sword_copy]);
+        assert_eq!(registry.write_scene(&copy), text);
+    }
+
+    #[test]
+    fn parents_from_scenes() {
+        let registry = registry();
+        let mut world = World::new(TestSet::new());
+        let labels = registry.load_scene(&mut world, r#"
+            entity player { Score(1) }
+            entity sword { Parent(@player), Score(2) }
+            entity gem { Parent(@sword) }
+            entity shield { Parent(@player) }
+        "#).unwrap();
+        let (player, sword, gem, shield) = (labels["player"], labels["sword"], labels["gem"], labels["shield"]);
+
+        assert_eq!(world.children(player), vec![sword, shield]);
+        assert_eq!(world.descendants(player), vec![sword, gem, shield]);
+
+        world.despawn_recursive(sword);
+        assert_eq!(world.children(player), vec![shield]);
+        assert!(!world.entities.is_alive(gem));
+    }
+
+    #[test]
+    fn empty_tuples() {
+        let registry = registry();
+        let mut world = World::new(TestSet::new());
+        let e = registry.load_scene(&mut world, "entity a { Marker(,), Score(1) }").unwrap()["a"];
+
+        let text = registry.write_scene(&world);
+        assert!(text.contains("Marker(,)"));
+
+        let mut copy = World::new(TestSet::new());
+        let e_copy = registry.load_scene(&mut copy, &text).unwrap()[&format!("e{}", e.id())];
+        assert!(copy.data.lock_storage::<Marker>().has(copy.entities.verify(e_copy).unwrap()));
+
+        // `()` is still the unit value, which isn't a tuple.
+        let err = registry.load_scene(&mut copy, "entity b { Marker() }").unwrap_err();
+        assert_eq!(err.message, "bad data for `Marker`: expected tuple, found unit");
+    }
+
+    #[test]
+    fn prefabs() {
+        let registry = registry();
+        let text = r#"
+            prefab goblin {
+                Stats { name: "goblin", damage: 2, tags: ["green"] },
+                Score(5),
+                child { Stats { name: "club", damage: 1, tags: [] } },
+            }
+
+            prefab chief: goblin { Stats { damage: 9 } }
+            entity boss: chief { Score(50) }
+        "#;
+
+        let mut world = World::new(TestSet::new());
+        let boss = registry.load_scene(&mut world, text).unwrap()["boss"];
+        assert_eq!(stats(&world, boss).name, "goblin");
+        assert_eq!(stats(&world, boss).damage, 9.0);
+        assert_eq!(world.children(boss).len(), 1);
+        assert_eq!(stats(&world, world.children(boss)[0]).name, "club");
+
+        let err = registry.load_prefabs(text).unwrap_err();
+        assert_eq!((err.line, err.column), (9, 13));
+
+        let prefabs = registry.load_prefabs(&text[..text.find("entity").unwrap()]).unwrap();
+        assert_eq!(prefabs["chief"].get("Score"), Some(&Value::Tuple(vec![Value::UInt(5)])));
+
+        // a partial struct without a base is reported where it was written.
+        let err = registry.load_prefabs("prefab a {
+  child { Stats { damage: 1 } },
+}").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 11));
+        assert_eq!(err.message, "bad data for `Stats`: missing field `name`");
+
+        let err = registry.load_prefabs("prefab a { Parent(@b) }").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 19));
+    }
+
+    #[test]
+    fn errors() {
+        let registry = registry();
+        let mut world = World::new(TestSet::new());
+        let error = |world: &mut World<TestSet>, text| registry.load_scene(world, text).unwrap_err();
+
+        let err = error(&mut world, "entity a {\n    Score(1),\n    Health(3),\n}");
+        assert_eq!((err.line, err.column), (3, 5));
+        assert!(err.message.starts_with("unknown component `Health`"));
+        assert!(err.message.contains("Stats, Score, Marker, Parent"));
+
+        let err = error(&mut world, "entity a { Parent(@b) }");
+        assert_eq!((err.line, err.column), (1, 19));
+
+        let err = error(&mut world, "entity a { Score(\"three\") }");
+        assert_eq!((err.line, err.column), (1, 12));
+        assert_eq!(err.to_string(), "1:12: bad data for `Score`: expected integer, found string");
+
+        let err = error(&mut world, "entity a {\n  Score(1) Score(2) }");
+        assert_eq!((err.line, err.column), (2, 12));
+
+        // values nested far too deeply are an error rather than overflowing the stack.
+        let deep = format!("entity a {{ Score = {}1 }}", "[".repeat(100000));
+        let err = error(&mut world, &deep);
+        assert_eq!((err.line, err.column), (1, 20 + MAX_DEPTH));
+        assert_eq!(err.message, "values nested too deeply");
+
+        let deep = format!("prefab a {{ Stats {{ tags: {}1 }} }}", "Some(".repeat(100000));
+        assert_eq!(registry.load_prefabs(&deep).unwrap_err().message, "values nested too deeply");
+
+        // nothing is left behind by a scene with errors.
+        error(&mut world, "entity a { Score(1) }\nentity b { Score(true) }");
+        assert!(world.entities.alive().is_empty());
+        assert_eq!(world.data.lock_storage::<Score>().entities().count(), 0);
+    }
+}
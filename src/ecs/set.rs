@@ -1,25 +1,18 @@
 //! Sets of component data.
 
+use std::any::{Any, TypeId};
+use std::hash::Hasher;
 use std::marker::PhantomData;
 use std::mem;
-use std::sync::{Mutex, MutexGuard};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use super::*;
 
-trait IsSame {
-    fn is_same() -> bool { false }
-  
-}
-
-impl<A, B> IsSame for (A, B) {}
-impl<A> IsSame for (A, A) {
-    #[inline]
-    fn is_same() -> bool { true }
-}
-
 #[inline]
-fn same<A, B>() -> bool {
-    <(A, B) as IsSame>::is_same()
+fn same<A: 'static, B: 'static>() -> bool {
+    TypeId::of::<A>() == TypeId::of::<B>()
 }
 
 /// The base case of a recursive struct.
@@ -41,6 +34,8 @@ pub trait Set: Sized + Sync {
     fn push_custom<T: Component>(self, storage: T::Storage) -> SetEntry<T, Self> {
         SetEntry {
             data: Mutex::new(storage),
+            version: AtomicUsize::new(0),
+            snapshot: Mutex::new(None),
             parent: self,
             _marker: PhantomData,
         }
@@ -53,34 +48,124 @@ pub trait Set: Sized + Sync {
     
     /// Get exclusive access to the storage for the given component by
     /// locking a mutex.
-    fn lock_storage<T: Component>(&self) -> MutexGuard<T::Storage>;
+    fn lock_storage<T: Component>(&self) -> StorageGuard<T>;
     
     /// Get exclusive access to the storage for the given component by
     /// accessing it through a mutable reference.
     fn get_storage_mut<T: Component>(&mut self) -> &mut T::Storage;
+    
+    /// A number which changes whenever the storage for the given component
+    /// is borrowed mutably, so it can't have changed while this stays the same.
+    fn storage_version<T: Component>(&self) -> usize;
+    
+    /// Destroy an entity's data in every storage within this set.
+    fn destroy(&mut self, e: Entity);
+    
+    /// Copy an entity's data in every storage within this set to another entity,
+    /// mapping the entity handles within. Components which can't be
+    /// duplicated are skipped.
+    fn clone_entity(&mut self, from: VerifiedEntity, to: VerifiedEntity, map: &mut FnMut(Entity) -> Entity);
+    
+    /// Move an entity's data in every storage within this set to an entity
+    /// in another set, mapping the entity handles within.
+    fn transfer(&mut self, from: VerifiedEntity, into: &mut Self, to: VerifiedEntity,
+                map: &mut FnMut(Entity) -> Entity);
+    
+    /// Capture the data in every storage within this set, pushing one entry
+    /// per storage. Storages which haven't changed since the last snapshot
+    /// share its entry rather than being copied again.
+    ///
+    /// Fails with the name of the first component found with data which
+    /// can't be duplicated.
+    fn snapshot(&self, out: &mut Vec<StorageSnapshot>) -> Result<(), &'static str>;
+    
+    /// Restore every storage within this set from the entries made by `snapshot`,
+    /// skipping any which haven't changed since.
+    fn restore(&mut self, from: &[StorageSnapshot], entities: &EntityManager);
+    
+    /// Hash the data of the given entities in every storage within this set,
+    /// visiting them in the order given.
+    ///
+    /// Panics if one of the entities has a component which can't be hashed.
+    fn hash_state(&self, entities: &[Entity], state: &mut Hasher);
+}
+
+/// The captured data of one storage, made by `Set::snapshot`.
+pub type StorageSnapshot = Arc<Any + Send + Sync>;
+
+// the data captured from a storage of `T`.
+struct Captured<T> {
+    data: Vec<(Entity, T)>,
+}
+
+/// A locked storage, returned by `Set::lock_storage`.
+///
+/// Borrowing the storage mutably counts as a change for snapshots.
+pub struct StorageGuard<'a, T: 'a + Component> {
+    guard: MutexGuard<'a, T::Storage>,
+    version: &'a AtomicUsize,
+}
+
+impl<'a, T: Component> Deref for StorageGuard<'a, T> {
+    type Target = T::Storage;
+
+    fn deref(&self) -> &T::Storage {
+        &self.guard
+    }
+}
+
+impl<'a, T: Component> DerefMut for StorageGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut T::Storage {
+        self.version.fetch_add(1, Ordering::Relaxed);
+        &mut self.guard
+    }
 }
 
 /// An entry in a set.
 pub struct SetEntry<T: Component, P: Set> {
     data: Mutex<T::Storage>,
+    // bumped whenever the storage may have changed.
+    version: AtomicUsize,
+    // the last snapshot taken or restored, with the version it matches.
+    snapshot: Mutex<Option<(usize, StorageSnapshot)>>,
     parent: P,
     _marker: PhantomData<T>,
 }
 
 impl Set for Empty {
-    fn lock_storage<T: Component>(&self) -> MutexGuard<T::Storage> {
+    fn lock_storage<T: Component>(&self) -> StorageGuard<T> {
         panic!("Attempted access of component not in set.");
     }
     
     fn get_storage_mut<T: Component>(&mut self) -> &mut T::Storage {
         panic!("Attempted access of component not in set.");
     }
+    
+    fn storage_version<T: Component>(&self) -> usize {
+        panic!("Attempted access of component not in set.");
+    }
+    
+    fn destroy(&mut self, _: Entity) {}
+    
+    fn clone_entity(&mut self, _: VerifiedEntity, _: VerifiedEntity, _: &mut FnMut(Entity) -> Entity) {}
+    
+    fn transfer(&mut self, _: VerifiedEntity, _: &mut Self, _: VerifiedEntity, _: &mut FnMut(Entity) -> Entity) {}
+    
+    fn snapshot(&self, _: &mut Vec<StorageSnapshot>) -> Result<(), &'static str> { Ok(()) }
+    
+    fn restore(&mut self, _: &[StorageSnapshot], _: &EntityManager) {}
+    
+    fn hash_state(&self, _: &[Entity], _: &mut Hasher) {}
 }
 
 impl<T: Component, P: Set> Set for SetEntry<T, P> {
-    fn lock_storage<C: Component>(&self) -> MutexGuard<C::Storage> {
+    fn lock_storage<C: Component>(&self) -> StorageGuard<C> {
         if same::<T, C>() {
-            unsafe { mem::transmute(self.data.lock().unwrap()) }
+            let guard: StorageGuard<T> = StorageGuard {
+                guard: self.data.lock().unwrap(),
+                version: &self.version,
+            };
+            unsafe { mem::transmute(guard) }
         } else {
             self.parent.lock_storage::<C>()
         }
@@ -88,11 +173,140 @@ impl<T: Component, P: Set> Set for SetEntry<T, P> {
     
     fn get_storage_mut<C: Component>(&mut self) -> &mut C::Storage {
         if same::<T, C>() {
+            *self.version.get_mut() += 1;
             unsafe { mem::transmute(self.data.get_mut().unwrap()) }
         } else {
             self.parent.get_storage_mut::<C>()
         }
     }
+    
+    fn storage_version<C: Component>(&self) -> usize {
+        if same::<T, C>() {
+            self.version.load(Ordering::Relaxed)
+        } else {
+            self.parent.storage_version::<C>()
+        }
+    }
+    
+    fn destroy(&mut self, e: Entity) {
+        *self.version.get_mut() += 1;
+        self.data.get_mut().unwrap().destroy(e);
+        self.parent.destroy(e);
+    }
+    
+    fn clone_entity(&mut self, from: VerifiedEntity, to: VerifiedEntity, map: &mut FnMut(Entity) -> Entity) {
+        *self.version.get_mut() += 1;
+        {
+            let storage = self.data.get_mut().unwrap();
+            let data = storage.get(from).and_then(Component::duplicate);
+            if let Some(mut data) = data {
+                data.map_entities(map);
+                storage.set(to, data);
+            }
+        }
+        
+        self.parent.clone_entity(from, to, map);
+    }
+    
+    fn transfer(&mut self, from: VerifiedEntity, into: &mut Self, to: VerifiedEntity,
+                map: &mut FnMut(Entity) -> Entity) {
+        *self.version.get_mut() += 1;
+        *into.version.get_mut() += 1;
+        if let Some(mut data) = self.data.get_mut().unwrap().remove(from) {
+            data.map_entities(map);
+            into.data.get_mut().unwrap().set(to, data);
+        }
+        
+        self.parent.transfer(from, &mut into.parent, to, map);
+    }
+    
+    fn snapshot(&self, out: &mut Vec<StorageSnapshot>) -> Result<(), &'static str> {
+        let mut last = self.snapshot.lock().unwrap();
+        let version = self.version.load(Ordering::Relaxed);
+        
+        let entry = match *last {
+            Some((v, ref entry)) if v == version => entry.clone(),
+            _ => {
+                let storage = self.data.lock().unwrap();
+                let mut captured = Captured { data: Vec::new() };
+                for e in storage.entities() {
+                    let data = storage.get(VerifiedEntity { inner: e, _marker: PhantomData })
+                        .and_then(Component::duplicate);
+                    match data {
+                        Some(data) => captured.data.push((e, data)),
+                        None => return Err(::std::any::type_name::<T>()),
+                    }
+                }
+                
+                let entry: StorageSnapshot = Arc::new(captured);
+                *last = Some((version, entry.clone()));
+                entry
+            }
+        };
+        
+        out.push(entry);
+        self.parent.snapshot(out)
+    }
+    
+    fn restore(&mut self, from: &[StorageSnapshot], entities: &EntityManager) {
+        let entry = &from[0];
+        let unchanged = match *self.snapshot.get_mut().unwrap() {
+            Some((v, ref last)) => v == *self.version.get_mut() && Arc::ptr_eq(last, entry),
+            None => false,
+        };
+        
+        let captured = entry.downcast_ref::<Captured<T>>().expect("snapshot is from a different set");
+        self.parent.restore(&from[1..], entities);
+        
+        if !unchanged {
+            let storage = self.data.get_mut().unwrap();
+            let stale: Vec<Entity> = storage.entities().collect();
+            for e in stale {
+                storage.destroy(e);
+            }
+            
+            // data for entities destroyed but not yet cleaned up is dropped.
+            for &(e, ref data) in &captured.data {
+                if let (Some(e), Some(data)) = (entities.verify(e), data.duplicate()) {
+                    storage.set(e, data);
+                }
+            }
+            
+            let version = *self.version.get_mut() + 1;
+            *self.version.get_mut() = version;
+            *self.snapshot.get_mut().unwrap() = Some((version, entry.clone()));
+        }
+    }
+    
+    fn hash_state(&self, entities: &[Entity], state: &mut Hasher) {
+        let hashed = {
+            // locked directly, since reading doesn't change the storage.
+            let storage = self.data.lock().unwrap();
+            let mut count = 0;
+            let hashed = entities.iter().all(|&e| {
+                match storage.get(VerifiedEntity { inner: e, _marker: PhantomData }) {
+                    Some(data) => {
+                        state.write_u32(e.id());
+                        state.write_u8(e.gen());
+                        count += 1;
+                        data.hash_state(state)
+                    }
+                    None => true,
+                }
+            });
+            
+            state.write_usize(count);
+            hashed
+        };
+        
+        // the storage is unlocked first, so it isn't poisoned.
+        if !hashed {
+            panic!("`{}` can't be hashed, since it doesn't implement `Component::hash_state`",
+                   ::std::any::type_name::<T>());
+        }
+        
+        self.parent.hash_state(entities, state);
+    }
 }
 
 /// A locked subset of a set.
@@ -103,7 +317,7 @@ impl<T: Component, P: Set> Set for SetEntry<T, P> {
 /// to be queried with a non-contained component than a `Set` which will encompass
 /// all components.
 pub trait LockedSubset: Sized {
-    fn push<T: Component>(self, guard: MutexGuard<T::Storage>) -> SubsetEntry<T, Self> {
+    fn push<T: Component>(self, guard: StorageGuard<T>) -> SubsetEntry<T, Self> {
         SubsetEntry {
             data: guard,
             parent: self,
@@ -122,7 +336,7 @@ pub trait LockedSubset: Sized {
 
 /// An entry in a subset.
 pub struct SubsetEntry<'a, T: 'a + Component, P: 'a + LockedSubset> {
-    data: MutexGuard<'a, T::Storage>,
+    data: StorageGuard<'a, T>,
     parent: P,
     _marker: PhantomData<T>
 }
@@ -0,0 +1,184 @@
+//! Snapshots of a world's state, for rolling back.
+//!
+//! A snapshot holds a copy of every storage's data, made through
+//! `Component::duplicate`, along with the entity allocator. Storages are
+//! tracked for changes, so a storage which hasn't been touched since the last
+//! snapshot shares that snapshot's data instead of being copied again, and
+//! restoring skips storages which already match.
+//!
+//! A storage counts as touched whenever it's borrowed mutably, even if nothing
+//! was written; locking it and only reading doesn't count. Snapshots can't be
+//! taken while any storage holds components which can't be duplicated.
+
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+use super::*;
+use super::set::{Set, StorageSnapshot};
+
+/// The state of a world at some point, which it can be restored to.
+pub struct Snapshot<S> {
+    entities: EntityManager,
+    storages: Vec<StorageSnapshot>,
+    _marker: PhantomData<fn() -> S>,
+}
+
+// cloning is cheap since the storage data is shared.
+impl<S> Clone for Snapshot<S> {
+    fn clone(&self) -> Self {
+        Snapshot {
+            entities: self.entities.clone(),
+            storages: self.storages.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+/// An error when taking a snapshot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// Some entity has a component of this type, which can't be duplicated.
+    NotDuplicable(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::NotDuplicable(name) => write!(f, "`{}` can't be duplicated", name),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn description(&self) -> &str {
+        match *self {
+            SnapshotError::NotDuplicable(_) => "component can't be duplicated",
+        }
+    }
+}
+
+impl<S: Set> World<S> {
+    /// Capture the state of every entity and component.
+    ///
+    /// Fails if some entity has a component which can't be duplicated.
+    pub fn snapshot(&self) -> Result<Snapshot<S>, SnapshotError> {
+        let mut storages = Vec::new();
+        self.data.snapshot(&mut storages).map_err(SnapshotError::NotDuplicable)?;
+
+        Ok(Snapshot {
+            entities: self.entities.clone(),
+            storages: storages,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Return to the state captured in a snapshot of this world.
+    ///
+    /// Entity handles are valid again exactly as they were when it was taken.
+    pub fn restore(&mut self, snapshot: &Snapshot<S>) {
+        self.data.restore(&snapshot.storages, &snapshot.entities);
+
+        // deterministic mode is a setting rather than state, so it stays as it is.
+        let ordered = self.entities.ordered;
+        self.entities = snapshot.entities.clone();
+        self.entities.ordered = ordered;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Position(i32);
+
+    impl Component for Position {
+        type Storage = DefaultStorage<Self>;
+
+        fn duplicate(&self) -> Option<Self> { Some(*self) }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    struct Team(u8);
+
+    impl Component for Team {
+        type Storage = DefaultStorage<Self>;
+
+        fn duplicate(&self) -> Option<Self> { Some(*self) }
+    }
+
+    // can't be duplicated.
+    struct Handle;
+
+    impl Component for Handle { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Position, Team, Handle }
+    }
+
+    fn position(world: &World<TestSet>, e: Entity) -> Option<i32> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Position>().get(e).map(|p| p.0)
+    }
+
+    #[test]
+    fn snapshot_and_restore() {
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().with(Position(0)).with(Team(1)).spawn();
+        let first = world.snapshot().unwrap();
+
+        // only positions change between these. the last listed storage comes first.
+        {
+            let a = world.entities.verify(a).unwrap();
+            world.data.get_storage_mut::<Position>().get_mut(a).unwrap().0 = 5;
+        }
+        let b = world.build_entity().with(Position(7)).spawn();
+        let second = world.snapshot().unwrap();
+        assert!(Arc::ptr_eq(&first.storages[1], &second.storages[1]));
+        assert!(!Arc::ptr_eq(&first.storages[2], &second.storages[2]));
+
+        // queries only read, so they don't count as changes.
+        let teams = world.handle().query::<(Team,)>().for_each(|_, (team,)| team.0);
+        assert_eq!(teams, vec![1]);
+        assert_eq!(position(&world, b), Some(7));
+        let third = world.snapshot().unwrap();
+        assert!(Arc::ptr_eq(&second.storages[1], &third.storages[1]));
+        assert!(Arc::ptr_eq(&second.storages[2], &third.storages[2]));
+
+        world.despawn(a);
+        world.restore(&first);
+        assert_eq!(position(&world, a), Some(0));
+        assert!(!world.entities.is_alive(b));
+
+        // handles come back exactly as they were.
+        world.restore(&second);
+        assert_eq!(position(&world, a), Some(5));
+        assert_eq!(position(&world, b), Some(7));
+        assert_eq!(world.entities.alive(), vec![a, b]);
+
+        let c = world.build_entity().with(Position(1)).spawn();
+        world.restore(&second);
+        assert!(!world.entities.is_alive(c));
+    }
+
+    #[test]
+    fn incomplete_snapshots() {
+        let mut world = World::new(TestSet::new());
+        let a = world.build_entity().with(Position(0)).with(Handle).spawn();
+        match world.snapshot() {
+            Err(SnapshotError::NotDuplicable(name)) => assert!(name.ends_with("Handle")),
+            Ok(_) => panic!("snapshot of a `Handle`"),
+        }
+
+        // without any handles, the world can be captured again.
+        world.despawn(a);
+        let b = world.build_entity().with(Position(3)).spawn();
+        let snapshot = world.snapshot().unwrap();
+        world.data.get_storage_mut::<Position>().destroy(b);
+        world.restore(&snapshot);
+        assert_eq!(position(&world, b), Some(3));
+    }
+}
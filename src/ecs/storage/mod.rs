@@ -0,0 +1,231 @@
+//! Alternative component storage data structures.
+//!
+//! `DefaultStorage` is the right choice for most components, but it allocates
+//! a lookup slot for every entity id up to the highest one it has seen.
+//! The storages in this module trade some iteration speed for a footprint
+//! proportional to the number of entities which actually have the component.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+
+use super::*;
+
+pub use self::octree::{OctreeStorage, Positioned3D, Aabb, Frustum, Plane, InAabb, InSphere, InFrustum};
+pub use self::quadtree::{QuadtreeStorage, Positioned2D, RadiusIndex, Rect, InRect, WithinRadius};
+pub use self::sorted::{SortedStorage, SortKey};
+pub use self::spatial_hash::{SpatialHashStorage, Neighbors};
+
+pub mod octree;
+pub mod quadtree;
+pub mod sorted;
+pub mod spatial_hash;
+mod tree;
+
+/// Component storage backed by a hash map.
+///
+/// Good for components which only a handful of entities carry,
+/// e.g. a `PlayerController`. Iteration order is unspecified.
+pub struct HashMapStorage<T: Component> {
+    data: HashMap<Entity, T>,
+}
+
+impl<T: Component> HashMapStorage<T> {
+    /// Create a new, empty storage.
+    pub fn new() -> Self {
+        HashMapStorage {
+            data: HashMap::new(),
+        }
+    }
+}
+
+impl<T: Component> Storage<T> for HashMapStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        self.data.insert(e.entity(), data);
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.data.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        self.data.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        self.data.get_mut(&e.entity())
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        self.data.remove(&e.entity())
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        self.data.remove(&e);
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.data.keys().cloned())
+    }
+}
+
+impl<T: Component> Default for HashMapStorage<T> {
+    fn default() -> Self {
+        HashMapStorage::new()
+    }
+}
+
+/// Component storage backed by an ordered map.
+///
+/// Like `HashMapStorage`, this only uses memory for entities which have
+/// the component, but iteration is deterministic: entities are visited
+/// in ascending order of their ids.
+pub struct BTreeStorage<T: Component> {
+    // keyed by entity id, so stale generations are replaced on `set`.
+    data: BTreeMap<u32, (Entity, T)>,
+}
+
+impl<T: Component> BTreeStorage<T> {
+    /// Create a new, empty storage.
+    pub fn new() -> Self {
+        BTreeStorage {
+            data: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T: Component> Storage<T> for BTreeStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        self.data.insert(e.entity().id(), (e.entity(), data));
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.get(e).is_some()
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        match self.data.get(&e.entity().id()) {
+            Some(&(entity, ref data)) if entity == e.entity() => Some(data),
+            _ => None,
+        }
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        match self.data.get_mut(&e.entity().id()) {
+            Some(&mut (entity, ref mut data)) if entity == e.entity() => Some(data),
+            _ => None,
+        }
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        if self.has(e) {
+            self.data.remove(&e.entity().id()).map(|(_, data)| data)
+        } else {
+            None
+        }
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        if self.data.get(&e.id()).is_some_and(|&(entity, _)| entity == e) {
+            self.data.remove(&e.id());
+        }
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.data.values().map(|&(e, _)| e))
+    }
+}
+
+impl<T: Component> Default for BTreeStorage<T> {
+    fn default() -> Self {
+        BTreeStorage::new()
+    }
+}
+
+/// Storage for zero-sized marker components, like `Boss` or `Frozen`.
+///
+/// Only the set of entities which have the component is stored.
+/// Every entity shares the same value.
+pub struct NullStorage<T: Component + Default> {
+    entities: HashSet<Entity>,
+    value: T,
+}
+
+impl<T: Component + Default> NullStorage<T> {
+    /// Create a new, empty storage.
+    ///
+    /// Panics if `T` is not zero-sized.
+    pub fn new() -> Self {
+        assert!(mem::size_of::<T>() == 0, "NullStorage can only store zero-sized components.");
+
+        NullStorage {
+            entities: HashSet::new(),
+            value: T::default(),
+        }
+    }
+}
+
+impl<T: Component + Default> Storage<T> for NullStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, _: T) {
+        self.entities.insert(e.entity());
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.entities.contains(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        if self.has(e) { Some(&self.value) } else { None }
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        if self.has(e) { Some(&mut self.value) } else { None }
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        if self.entities.remove(&e.entity()) { Some(T::default()) } else { None }
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        self.entities.remove(&e);
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.entities.iter().cloned())
+    }
+}
+
+impl<T: Component + Default> Default for NullStorage<T> {
+    fn default() -> Self {
+        NullStorage::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Id(u32);
+
+    impl Component for Id {
+        type Storage = BTreeStorage<Self>;
+    }
+
+    #[test]
+    fn btree_iterates_in_entity_order() {
+        let mut manager = EntityManager::new();
+        let entities: Vec<_> = (0..4).map(|_| manager.next()).collect();
+
+        let mut storage = BTreeStorage::new();
+        for &e in entities.iter().rev() {
+            storage.set(manager.verify(e).unwrap(), Id(e.id()));
+        }
+
+        let iterated: Vec<_> = storage.entities().collect();
+        assert_eq!(iterated, entities);
+        assert_eq!(storage.get(manager.verify(entities[2]).unwrap()).unwrap().0, entities[2].id());
+
+        storage.remove(manager.verify(entities[1]).unwrap());
+        assert!(!storage.has(manager.verify(entities[1]).unwrap()));
+        assert_eq!(storage.entities().count(), 3);
+    }
+}
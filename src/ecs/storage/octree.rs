@@ -0,0 +1,470 @@
+//! Octree storage for 3D positional components.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use super::super::*;
+use super::super::query::Filter;
+use super::tree::{Bounds, TreeIndex};
+
+// half-extent of the bounds used by `OctreeStorage::default()`.
+const DEFAULT_EXTENT: f32 = 8192.0;
+
+/// A point or vector in 3D space.
+pub type Vec3 = (f32, f32, f32);
+
+fn dot(a: Vec3, b: Vec3) -> f32 {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+fn distance_sq(a: Vec3, b: Vec3) -> f32 {
+    let d = (a.0 - b.0, a.1 - b.1, a.2 - b.2);
+    dot(d, d)
+}
+
+/// A component which has a position in space.
+pub trait Positioned3D {
+    /// The position of this component as `(x, y, z)`.
+    fn position(&self) -> Vec3;
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Create a box from its minimum and maximum corners.
+    pub fn new(min: Vec3, max: Vec3) -> Self {
+        Aabb { min: min, max: max }
+    }
+
+    /// Whether the point lies within this box, inclusive of the faces.
+    pub fn contains(&self, p: Vec3) -> bool {
+        p.0 >= self.min.0 && p.0 <= self.max.0 &&
+        p.1 >= self.min.1 && p.1 <= self.max.1 &&
+        p.2 >= self.min.2 && p.2 <= self.max.2
+    }
+
+    /// Whether this box overlaps another.
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.0 <= other.max.0 && self.max.0 >= other.min.0 &&
+        self.min.1 <= other.max.1 && self.max.1 >= other.min.1 &&
+        self.min.2 <= other.max.2 && self.max.2 >= other.min.2
+    }
+
+    /// Whether this box overlaps the given sphere.
+    pub fn intersects_sphere(&self, center: Vec3, radius: f32) -> bool {
+        let closest = (
+            center.0.max(self.min.0).min(self.max.0),
+            center.1.max(self.min.1).min(self.max.1),
+            center.2.max(self.min.2).min(self.max.2),
+        );
+
+        distance_sq(center, closest) <= radius * radius
+    }
+
+    fn center(&self) -> Vec3 {
+        (
+            (self.min.0 + self.max.0) / 2.0,
+            (self.min.1 + self.max.1) / 2.0,
+            (self.min.2 + self.max.2) / 2.0,
+        )
+    }
+
+    // the child box with the given octant index. see `octant()`.
+    fn child(&self, idx: usize) -> Aabb {
+        let c = self.center();
+        let (x0, x1) = if idx & 1 == 0 { (self.min.0, c.0) } else { (c.0, self.max.0) };
+        let (y0, y1) = if idx & 2 == 0 { (self.min.1, c.1) } else { (c.1, self.max.1) };
+        let (z0, z1) = if idx & 4 == 0 { (self.min.2, c.2) } else { (c.2, self.max.2) };
+
+        Aabb::new((x0, y0, z0), (x1, y1, z1))
+    }
+
+    // the index of the octant containing the point.
+    fn octant(&self, p: Vec3) -> usize {
+        let c = self.center();
+        let mut idx = 0;
+        if p.0 >= c.0 { idx |= 1 }
+        if p.1 >= c.1 { idx |= 2 }
+        if p.2 >= c.2 { idx |= 4 }
+        idx
+    }
+}
+
+impl Bounds for Aabb {
+    type Point = Vec3;
+
+    const MAX_DEPTH: usize = 10;
+
+    fn contains(&self, p: Vec3) -> bool {
+        Aabb::contains(self, p)
+    }
+
+    fn split(&self) -> Vec<Aabb> {
+        (0..8).map(|idx| self.child(idx)).collect()
+    }
+
+    fn child_index(&self, p: Vec3) -> usize {
+        self.octant(p)
+    }
+}
+
+/// A plane described by `dot(normal, p) + d = 0`.
+///
+/// Points with `dot(normal, p) + d >= 0` are on the inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub d: f32,
+}
+
+impl Plane {
+    /// Create a plane from a normal facing inwards and a distance.
+    pub fn new(normal: Vec3, d: f32) -> Self {
+        Plane { normal: normal, d: d }
+    }
+
+    fn distance(&self, p: Vec3) -> f32 {
+        dot(self.normal, p) + self.d
+    }
+}
+
+/// A view frustum, as the intersection of six planes facing inwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frustum {
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Create a frustum from its six planes.
+    pub fn new(planes: [Plane; 6]) -> Self {
+        Frustum { planes: planes }
+    }
+
+    /// Extract the frustum planes from a column-major view-projection matrix.
+    pub fn from_matrix(m: &[[f32; 4]; 4]) -> Self {
+        // row `i` of the matrix.
+        let row = |i: usize| (m[0][i], m[1][i], m[2][i], m[3][i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        let plane = |a: (f32, f32, f32, f32), b: (f32, f32, f32, f32), sign: f32| {
+            let (x, y, z, w) = (a.0 + sign * b.0, a.1 + sign * b.1, a.2 + sign * b.2, a.3 + sign * b.3);
+            let len = (x * x + y * y + z * z).sqrt();
+            Plane::new((x / len, y / len, z / len), w / len)
+        };
+
+        Frustum::new([
+            plane(r3, r0, 1.0), plane(r3, r0, -1.0),
+            plane(r3, r1, 1.0), plane(r3, r1, -1.0),
+            plane(r3, r2, 1.0), plane(r3, r2, -1.0),
+        ])
+    }
+
+    /// Whether the point lies within the frustum.
+    pub fn contains(&self, p: Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.distance(p) >= 0.0)
+    }
+
+    /// Whether the box might overlap the frustum.
+    ///
+    /// This is conservative: some boxes near the frustum's corners
+    /// will be reported as intersecting even though they don't.
+    pub fn intersects(&self, b: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal.
+            let n = plane.normal;
+            let corner = (
+                if n.0 >= 0.0 { b.max.0 } else { b.min.0 },
+                if n.1 >= 0.0 { b.max.1 } else { b.min.1 },
+                if n.2 >= 0.0 { b.max.2 } else { b.min.2 },
+            );
+
+            plane.distance(corner) >= 0.0
+        })
+    }
+}
+
+/// Component storage which indexes data by position in an octree.
+///
+/// Region queries with the `InAabb`, `InSphere` and `InFrustum` filters
+/// only visit the parts of the tree which overlap the region.
+/// Entities whose position lies outside of the tree's bounds are still stored,
+/// but are tested linearly by every query.
+///
+/// Positions changed through `get_mut` are picked up by the next mutable access
+/// to the storage. The tree is rebalanced incrementally as entities move: entities
+/// which stay within their leaf are updated in place, leaves are split as
+/// they fill up and merged back together as they empty.
+pub struct OctreeStorage<T: Component + Positioned3D> {
+    data: HashMap<Entity, T>,
+    index: TreeIndex<Aabb>,
+}
+
+impl<T: Component + Positioned3D> OctreeStorage<T> {
+    /// Create a new storage whose tree covers the given bounds.
+    pub fn new(bounds: Aabb) -> Self {
+        OctreeStorage {
+            data: HashMap::new(),
+            index: TreeIndex::new(bounds),
+        }
+    }
+
+    /// The bounds covered by the tree.
+    pub fn bounds(&self) -> Aabb {
+        self.index.bounds()
+    }
+
+    /// Re-index all entities whose positions may have changed.
+    pub fn reindex(&mut self) {
+        let data = &self.data;
+        self.index.reindex(|e| data.get(&e).map(Positioned3D::position));
+    }
+
+    /// All entities whose position lies within the box.
+    pub fn in_aabb(&self, aabb: Aabb) -> Vec<Entity> {
+        self.query(|b| b.intersects(&aabb), |p| aabb.contains(p))
+    }
+
+    /// All entities whose position lies within `radius` of `center`.
+    pub fn in_sphere(&self, center: Vec3, radius: f32) -> Vec<Entity> {
+        let r2 = radius * radius;
+        self.query(|b| b.intersects_sphere(center, radius),
+                   |p| distance_sq(center, p) <= r2)
+    }
+
+    /// All entities whose position lies within the frustum.
+    pub fn in_frustum(&self, frustum: &Frustum) -> Vec<Entity> {
+        self.query(|b| frustum.intersects(b), |p| frustum.contains(p))
+    }
+
+    fn query<V, P>(&self, visit: V, test: P) -> Vec<Entity>
+    where V: Fn(&Aabb) -> bool, P: Fn(Vec3) -> bool {
+        let data = &self.data;
+        self.index.query(visit, test, |e| data.get(&e).map(Positioned3D::position))
+    }
+}
+
+impl<T: Component + Positioned3D> Storage<T> for OctreeStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        self.reindex();
+
+        let e = e.entity();
+        self.index.insert(e, data.position());
+        self.data.insert(e, data);
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.data.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        self.data.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        self.reindex();
+
+        // the caller may move the entity, so its place in the tree
+        // is revisited on the next mutable access.
+        let e = e.entity();
+        let data = self.data.get_mut(&e)?;
+        self.index.invalidate(e);
+        Some(data)
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        self.reindex();
+
+        let e = e.entity();
+        self.index.remove(e);
+        self.data.remove(&e)
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        self.index.remove(e);
+        self.data.remove(&e);
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.data.keys().cloned())
+    }
+}
+
+impl<T: Component + Positioned3D> Default for OctreeStorage<T> {
+    fn default() -> Self {
+        let e = DEFAULT_EXTENT;
+        OctreeStorage::new(Aabb::new((-e, -e, -e), (e, e, e)))
+    }
+}
+
+/// A filter for entities whose position lies within a box.
+pub struct InAabb<T> {
+    aabb: Aabb,
+    _marker: PhantomData<T>,
+}
+
+impl<T> InAabb<T> {
+    /// Create a new filter for the given box.
+    pub fn new(aabb: Aabb) -> Self {
+        InAabb {
+            aabb: aabb,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for InAabb<T>
+where T: Component<Storage=OctreeStorage<T>> + Positioned3D {
+    type Component = T;
+
+    fn pred(&self, storage: &OctreeStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| self.aabb.contains(data.position()))
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a OctreeStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.in_aabb(self.aabb).into_iter())
+    }
+}
+
+/// A filter for entities whose position lies within a sphere.
+pub struct InSphere<T> {
+    center: Vec3,
+    radius: f32,
+    _marker: PhantomData<T>,
+}
+
+impl<T> InSphere<T> {
+    /// Create a new filter for the sphere with the given center and radius.
+    pub fn new(center: Vec3, radius: f32) -> Self {
+        InSphere {
+            center: center,
+            radius: radius,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for InSphere<T>
+where T: Component<Storage=OctreeStorage<T>> + Positioned3D {
+    type Component = T;
+
+    fn pred(&self, storage: &OctreeStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| {
+            distance_sq(self.center, data.position()) <= self.radius * self.radius
+        })
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a OctreeStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.in_sphere(self.center, self.radius).into_iter())
+    }
+}
+
+/// A filter for entities whose position lies within a view frustum.
+pub struct InFrustum<T> {
+    frustum: Frustum,
+    _marker: PhantomData<T>,
+}
+
+impl<T> InFrustum<T> {
+    /// Create a new filter for the given frustum.
+    pub fn new(frustum: Frustum) -> Self {
+        InFrustum {
+            frustum: frustum,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for InFrustum<T>
+where T: Component<Storage=OctreeStorage<T>> + Positioned3D {
+    type Component = T;
+
+    fn pred(&self, storage: &OctreeStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| self.frustum.contains(data.position()))
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a OctreeStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.in_frustum(&self.frustum).into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Pos(f32, f32, f32);
+
+    impl Positioned3D for Pos {
+        fn position(&self) -> Vec3 { (self.0, self.1, self.2) }
+    }
+
+    impl Component for Pos {
+        type Storage = OctreeStorage<Pos>;
+    }
+
+    #[test]
+    fn moving_entities_stay_queryable() {
+        let mut manager = EntityManager::new();
+        let mut storage = OctreeStorage::new(Aabb::new((0.0, 0.0, 0.0), (64.0, 64.0, 64.0)));
+
+        let mut entities = Vec::new();
+        for i in 0..64 {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Pos(i as f32, 1.0, 1.0));
+            entities.push(e);
+        }
+
+        assert_eq!(storage.in_sphere((0.0, 1.0, 1.0), 3.5).len(), 4);
+
+        // move everything across the tree, one entity at a time.
+        for &e in &entities {
+            storage.get_mut(manager.verify(e).unwrap()).unwrap().1 = 60.0;
+            assert_eq!(InSphere::<Pos>::new((0.0, 1.0, 1.0), 3.5).candidates(&storage).count() +
+                       InSphere::<Pos>::new((0.0, 60.0, 1.0), 3.5).candidates(&storage).count(), 4);
+        }
+
+        storage.reindex();
+        assert!(storage.in_sphere((0.0, 1.0, 1.0), 3.5).is_empty());
+        assert_eq!(storage.in_aabb(Aabb::new((0.0, 59.0, 0.0), (9.5, 61.0, 2.0))).len(), 10);
+
+        // a frustum looking down the x axis from the origin, cut off at x = 20.
+        let frustum = Frustum::new([
+            Plane::new((1.0, 0.0, 0.0), 0.0), Plane::new((-1.0, 0.0, 0.0), 20.0),
+            Plane::new((0.0, 1.0, 0.0), 0.0), Plane::new((0.0, -1.0, 0.0), 64.0),
+            Plane::new((0.0, 0.0, 1.0), 0.0), Plane::new((0.0, 0.0, -1.0), 64.0),
+        ]);
+        assert_eq!(storage.in_frustum(&frustum).len(), 21);
+    }
+
+    #[test]
+    fn entities_move_in_and_out_of_bounds() {
+        let mut manager = EntityManager::new();
+        let mut storage = OctreeStorage::new(Aabb::new((0.0, 0.0, 0.0), (64.0, 64.0, 64.0)));
+
+        let e = manager.next();
+        let v = manager.verify(e).unwrap();
+        storage.set(v, Pos(-10.0, 1.0, 1.0));
+        assert!(storage.index.is_outside(e));
+
+        // moving inside puts it into the tree on the next mutable access.
+        storage.get_mut(v).unwrap().0 = 10.0;
+        assert_eq!(storage.in_sphere((10.0, 1.0, 1.0), 1.0), vec![e]);
+        storage.reindex();
+        assert!(!storage.index.is_outside(e));
+        assert_eq!(storage.in_sphere((10.0, 1.0, 1.0), 1.0), vec![e]);
+
+        storage.get_mut(v).unwrap().0 = 100.0;
+        storage.reindex();
+        assert!(storage.index.is_outside(e));
+        assert!(storage.in_sphere((10.0, 1.0, 1.0), 1.0).is_empty());
+        assert_eq!(storage.in_sphere((100.0, 1.0, 1.0), 1.0), vec![e]);
+    }
+}
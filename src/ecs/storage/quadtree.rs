@@ -0,0 +1,363 @@
+//! Quadtree storage for 2D positional components.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use super::super::*;
+use super::super::query::Filter;
+use super::tree::{Bounds, TreeIndex};
+
+// half-extent of the bounds used by `QuadtreeStorage::default()`.
+const DEFAULT_EXTENT: f32 = 8192.0;
+
+/// A component which has a position on a plane.
+pub trait Positioned2D {
+    /// The position of this component as `(x, y)`.
+    fn position(&self) -> (f32, f32);
+}
+
+/// A storage which can find the entities near a point.
+///
+/// This lets code such as replication interest management work
+/// with any of the 2D spatial storages.
+pub trait RadiusIndex {
+    /// All entities whose position lies within `radius` of `center`.
+    fn within_radius(&self, center: (f32, f32), radius: f32) -> Vec<Entity>;
+}
+
+/// An axis-aligned rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rect {
+    pub min: (f32, f32),
+    pub max: (f32, f32),
+}
+
+impl Rect {
+    /// Create a rectangle from its minimum and maximum corners.
+    pub fn new(min: (f32, f32), max: (f32, f32)) -> Self {
+        Rect { min: min, max: max }
+    }
+
+    /// Whether the point lies within this rectangle, inclusive of the edges.
+    pub fn contains(&self, p: (f32, f32)) -> bool {
+        p.0 >= self.min.0 && p.0 <= self.max.0 &&
+        p.1 >= self.min.1 && p.1 <= self.max.1
+    }
+
+    /// Whether this rectangle overlaps another.
+    pub fn intersects(&self, other: &Rect) -> bool {
+        self.min.0 <= other.max.0 && self.max.0 >= other.min.0 &&
+        self.min.1 <= other.max.1 && self.max.1 >= other.min.1
+    }
+
+    /// Whether this rectangle overlaps the given circle.
+    pub fn intersects_circle(&self, center: (f32, f32), radius: f32) -> bool {
+        let dx = center.0 - center.0.max(self.min.0).min(self.max.0);
+        let dy = center.1 - center.1.max(self.min.1).min(self.max.1);
+        dx * dx + dy * dy <= radius * radius
+    }
+
+    fn center(&self) -> (f32, f32) {
+        ((self.min.0 + self.max.0) / 2.0, (self.min.1 + self.max.1) / 2.0)
+    }
+
+    // split into quadrants, ordered so that `quadrant()` indexes them.
+    fn split(&self) -> [Rect; 4] {
+        let c = self.center();
+        [
+            Rect::new(self.min, c),
+            Rect::new((c.0, self.min.1), (self.max.0, c.1)),
+            Rect::new((self.min.0, c.1), (c.0, self.max.1)),
+            Rect::new(c, self.max),
+        ]
+    }
+
+    // the index of the quadrant containing the point.
+    fn quadrant(&self, p: (f32, f32)) -> usize {
+        let c = self.center();
+        let mut idx = 0;
+        if p.0 >= c.0 { idx += 1 }
+        if p.1 >= c.1 { idx += 2 }
+        idx
+    }
+}
+
+impl Bounds for Rect {
+    type Point = (f32, f32);
+
+    const MAX_DEPTH: usize = 12;
+
+    fn contains(&self, p: (f32, f32)) -> bool {
+        Rect::contains(self, p)
+    }
+
+    fn split(&self) -> Vec<Rect> {
+        Rect::split(self).to_vec()
+    }
+
+    fn child_index(&self, p: (f32, f32)) -> usize {
+        self.quadrant(p)
+    }
+}
+
+fn distance_sq(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+    dx * dx + dy * dy
+}
+
+/// Component storage which indexes data by position in a quadtree.
+///
+/// Region queries with the `InRect` and `WithinRadius` filters only visit the
+/// parts of the tree that overlap the region.
+/// Entities whose position lies outside of the tree's bounds are still stored,
+/// but are tested linearly by every query.
+///
+/// Positions changed through `get_mut` are picked up lazily: the entity is
+/// re-indexed by the next mutable access to the storage.
+pub struct QuadtreeStorage<T: Component + Positioned2D> {
+    data: HashMap<Entity, T>,
+    index: TreeIndex<Rect>,
+}
+
+impl<T: Component + Positioned2D> QuadtreeStorage<T> {
+    /// Create a new storage whose tree covers the given bounds.
+    pub fn new(bounds: Rect) -> Self {
+        QuadtreeStorage {
+            data: HashMap::new(),
+            index: TreeIndex::new(bounds),
+        }
+    }
+
+    /// The bounds covered by the tree.
+    pub fn bounds(&self) -> Rect {
+        self.index.bounds()
+    }
+
+    /// Re-index all entities whose positions may have changed.
+    pub fn reindex(&mut self) {
+        let data = &self.data;
+        self.index.reindex(|e| data.get(&e).map(Positioned2D::position));
+    }
+
+    /// All entities whose position lies within the rectangle.
+    pub fn in_rect(&self, rect: Rect) -> Vec<Entity> {
+        self.query(|b| b.intersects(&rect), |p| rect.contains(p))
+    }
+
+    /// All entities whose position lies within `radius` of `center`.
+    pub fn within_radius(&self, center: (f32, f32), radius: f32) -> Vec<Entity> {
+        let r2 = radius * radius;
+        self.query(|b| b.intersects_circle(center, radius),
+                   |p| distance_sq(center, p) <= r2)
+    }
+
+    fn query<V, P>(&self, visit: V, test: P) -> Vec<Entity>
+    where V: Fn(&Rect) -> bool, P: Fn((f32, f32)) -> bool {
+        let data = &self.data;
+        self.index.query(visit, test, |e| data.get(&e).map(Positioned2D::position))
+    }
+}
+
+impl<T: Component + Positioned2D> Storage<T> for QuadtreeStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        self.reindex();
+
+        let e = e.entity();
+        self.index.insert(e, data.position());
+        self.data.insert(e, data);
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.data.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        self.data.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        self.reindex();
+
+        // the caller may move the entity, so its place in the tree
+        // is revisited on the next mutable access.
+        let e = e.entity();
+        let data = self.data.get_mut(&e)?;
+        self.index.invalidate(e);
+        Some(data)
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        self.reindex();
+
+        let e = e.entity();
+        self.index.remove(e);
+        self.data.remove(&e)
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        self.index.remove(e);
+        self.data.remove(&e);
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.data.keys().cloned())
+    }
+}
+
+impl<T: Component + Positioned2D> RadiusIndex for QuadtreeStorage<T> {
+    fn within_radius(&self, center: (f32, f32), radius: f32) -> Vec<Entity> {
+        QuadtreeStorage::within_radius(self, center, radius)
+    }
+}
+
+impl<T: Component + Positioned2D> Default for QuadtreeStorage<T> {
+    fn default() -> Self {
+        QuadtreeStorage::new(Rect::new(
+            (-DEFAULT_EXTENT, -DEFAULT_EXTENT),
+            (DEFAULT_EXTENT, DEFAULT_EXTENT),
+        ))
+    }
+}
+
+/// A filter for entities whose position lies within a rectangle.
+pub struct InRect<T> {
+    rect: Rect,
+    _marker: PhantomData<T>,
+}
+
+impl<T> InRect<T> {
+    /// Create a new filter for the given rectangle.
+    pub fn new(rect: Rect) -> Self {
+        InRect {
+            rect: rect,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for InRect<T>
+where T: Component<Storage=QuadtreeStorage<T>> + Positioned2D {
+    type Component = T;
+
+    fn pred(&self, storage: &QuadtreeStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| self.rect.contains(data.position()))
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a QuadtreeStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.in_rect(self.rect).into_iter())
+    }
+}
+
+/// A filter for entities whose position lies within a circle.
+pub struct WithinRadius<T> {
+    center: (f32, f32),
+    radius: f32,
+    _marker: PhantomData<T>,
+}
+
+impl<T> WithinRadius<T> {
+    /// Create a new filter for the circle with the given center and radius.
+    pub fn new(center: (f32, f32), radius: f32) -> Self {
+        WithinRadius {
+            center: center,
+            radius: radius,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for WithinRadius<T>
+where T: Component<Storage=QuadtreeStorage<T>> + Positioned2D {
+    type Component = T;
+
+    fn pred(&self, storage: &QuadtreeStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| {
+            distance_sq(self.center, data.position()) <= self.radius * self.radius
+        })
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a QuadtreeStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.within_radius(self.center, self.radius).into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Pos(f32, f32);
+
+    impl Positioned2D for Pos {
+        fn position(&self) -> (f32, f32) { (self.0, self.1) }
+    }
+
+    impl Component for Pos {
+        type Storage = QuadtreeStorage<Pos>;
+    }
+
+    #[test]
+    fn region_queries() {
+        let mut manager = EntityManager::new();
+        let mut storage = QuadtreeStorage::new(Rect::new((0.0, 0.0), (100.0, 100.0)));
+
+        let mut entities = Vec::new();
+        for i in 0..100 {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Pos(i as f32, i as f32));
+            entities.push(e);
+        }
+
+        let outside = manager.next();
+        storage.set(manager.verify(outside).unwrap(), Pos(-5.0, -5.0));
+
+        let mut found = storage.in_rect(Rect::new((10.0, 10.0), (19.5, 19.5)));
+        found.sort_by_key(|e| e.id());
+        assert_eq!(found, &entities[10..20]);
+
+        let found = storage.within_radius((0.0, 0.0), 8.0);
+        assert!(found.contains(&outside));
+        assert_eq!(found.len(), 7);
+
+        // moving an entity through `get_mut` is reflected in queries.
+        storage.get_mut(manager.verify(entities[50]).unwrap()).unwrap().0 = 15.0;
+        storage.get_mut(manager.verify(entities[51]).unwrap()).unwrap().1 = 15.0;
+        let filter = InRect::<Pos>::new(Rect::new((14.5, 14.5), (15.5, 15.5)));
+        assert_eq!(filter.candidates(&storage).count(), 1);
+        storage.reindex();
+        assert_eq!(storage.in_rect(Rect::new((14.5, 49.5), (15.5, 50.5))), vec![entities[50]]);
+
+        for &e in &entities {
+            storage.remove(manager.verify(e).unwrap());
+        }
+
+        assert!(storage.index.is_collapsed());
+        assert_eq!(storage.entities().count(), 1);
+    }
+
+    component_set! {
+        struct TestSet { Pos => QuadtreeStorage::new(Rect::new((0.0, 0.0), (100.0, 100.0))) }
+    }
+
+    #[test]
+    fn filtered_queries() {
+        let mut world = World::new(TestSet::new());
+        let es: Vec<Entity> = (0..10)
+            .map(|i| world.build_entity().with(Pos(i as f32 * 10.0, 5.0)).spawn())
+            .collect();
+
+        let mut found = world.handle().query::<()>()
+            .with_filtered(InRect::<Pos>::new(Rect::new((15.0, 0.0), (45.0, 10.0))))
+            .for_each(|e, (p,)| (e.entity(), p.0));
+        found.sort_by_key(|&(e, _)| e.id());
+        assert_eq!(found, vec![(es[2], 20.0), (es[3], 30.0), (es[4], 40.0)]);
+
+        let mut found = world.handle().query::<()>()
+            .with_filtered(WithinRadius::<Pos>::new((50.0, 5.0), 10.5))
+            .for_each(|e, _| e.entity());
+        found.sort_by_key(|e| e.id());
+        assert_eq!(found, &es[4..7]);
+    }
+}
@@ -0,0 +1,186 @@
+//! Storage which keeps components ordered by a key.
+
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Mutex;
+
+use super::super::*;
+
+/// A component which can be ordered by a key.
+pub trait SortKey<K: Ord> {
+    /// The key this component is ordered by.
+    fn sort_key(&self) -> K;
+}
+
+// the iteration order, sorted lazily.
+struct Order {
+    entities: Vec<Entity>,
+    dirty: bool,
+}
+
+/// Component storage whose `entities()` are yielded in ascending order of key.
+///
+/// Entities with equal keys are ordered by id. Since queries iterate in the
+/// order of the storage of their first component, a query which leads with a
+/// sorted component will visit entities in key order, in deterministic mode too.
+/// This is useful for render order, turn order, and the like.
+///
+/// Mutation through `set` or `get_mut` only marks the order as stale;
+/// it is re-sorted the next time the entities are iterated over.
+pub struct SortedStorage<T: Component + SortKey<K>, K: Ord> {
+    data: HashMap<Entity, T>,
+    order: Mutex<Order>,
+    // `K` is only ever produced, so this doesn't affect `Send` or `Sync`.
+    _marker: PhantomData<fn() -> K>,
+}
+
+impl<T: Component + SortKey<K>, K: Ord> SortedStorage<T, K> {
+    /// Create a new, empty storage.
+    pub fn new() -> Self {
+        SortedStorage {
+            data: HashMap::new(),
+            order: Mutex::new(Order { entities: Vec::new(), dirty: false }),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sort the entities now, rather than when they are next iterated over.
+    pub fn sort(&mut self) {
+        let order = self.order.get_mut().unwrap();
+        Self::sort_order(&self.data, order);
+    }
+
+    fn sort_order(data: &HashMap<Entity, T>, order: &mut Order) {
+        if !order.dirty { return }
+
+        order.entities.sort_by(|a, b| {
+            data[a].sort_key().cmp(&data[b].sort_key()).then(a.id().cmp(&b.id()))
+        });
+        order.dirty = false;
+    }
+
+    fn mark_dirty(&mut self) {
+        self.order.get_mut().unwrap().dirty = true;
+    }
+}
+
+impl<T: Component + SortKey<K>, K: Ord> Storage<T> for SortedStorage<T, K> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        let e = e.entity();
+        if self.data.insert(e, data).is_none() {
+            self.order.get_mut().unwrap().entities.push(e);
+        }
+
+        self.mark_dirty();
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.data.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        self.data.get(&e.entity())
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        if !self.data.contains_key(&e.entity()) { return None }
+
+        self.mark_dirty();
+        self.data.get_mut(&e.entity())
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        let e = e.entity();
+        let data = self.data.remove(&e);
+        if data.is_some() {
+            // removal keeps the rest of the entities in order.
+            self.order.get_mut().unwrap().entities.retain(|x| *x != e);
+        }
+
+        data
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        if self.data.remove(&e).is_some() {
+            self.order.get_mut().unwrap().entities.retain(|x| *x != e);
+        }
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        let mut order = self.order.lock().unwrap();
+        Self::sort_order(&self.data, &mut order);
+
+        Box::new(order.entities.clone().into_iter())
+    }
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+}
+
+impl<T: Component + SortKey<K>, K: Ord> Default for SortedStorage<T, K> {
+    fn default() -> Self {
+        SortedStorage::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Depth(i32);
+
+    impl Component for Depth {
+        type Storage = SortedStorage<Self, i32>;
+    }
+
+    impl SortKey<i32> for Depth {
+        fn sort_key(&self) -> i32 { self.0 }
+    }
+
+    #[test]
+    fn iterates_in_key_order() {
+        let mut manager = EntityManager::new();
+        let mut storage = SortedStorage::new();
+
+        let entities: Vec<_> = [3, -1, 2, 2].iter().map(|&depth| {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Depth(depth));
+            e
+        }).collect();
+
+        let order: Vec<_> = storage.entities().collect();
+        assert_eq!(order, vec![entities[1], entities[2], entities[3], entities[0]]);
+
+        storage.get_mut(manager.verify(entities[0]).unwrap()).unwrap().0 = -5;
+        storage.remove(manager.verify(entities[2]).unwrap());
+
+        let order: Vec<_> = storage.entities().collect();
+        assert_eq!(order, vec![entities[0], entities[1], entities[3]]);
+    }
+
+    struct Name(&'static str);
+    impl Component for Name { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Depth, Name }
+    }
+
+    #[test]
+    fn queries_iterate_in_key_order() {
+        let mut world = World::new(TestSet::new());
+        for &(depth, name) in &[(3, "back"), (-1, "front"), (1, "middle")] {
+            world.build_entity().with(Depth(depth)).with(Name(name)).spawn();
+        }
+        world.build_entity().with(Depth(0)).spawn();
+
+        let names = world.handle().query::<(Depth, Name)>().for_each(|_, (_, name)| name.0);
+        assert_eq!(names, vec!["front", "middle", "back"]);
+
+        // key order is already deterministic, so it isn't replaced by id order.
+        world.set_deterministic(true);
+        let names = world.handle().query::<(Depth, Name)>().for_each(|_, (_, name)| name.0);
+        assert_eq!(names, vec!["front", "middle", "back"]);
+    }
+}
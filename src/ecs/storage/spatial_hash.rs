@@ -0,0 +1,377 @@
+//! Uniform grid storage for 2D positional components.
+
+use std::collections::{HashMap, HashSet};
+use std::marker::PhantomData;
+
+use super::super::*;
+use super::super::query::Filter;
+use super::quadtree::{Positioned2D, RadiusIndex};
+
+// cell size used by `SpatialHashStorage::default()`.
+const DEFAULT_CELL_SIZE: f32 = 16.0;
+
+type Cell = (i32, i32);
+
+fn distance_sq(a: (f32, f32), b: (f32, f32)) -> f32 {
+    let (dx, dy) = (a.0 - b.0, a.1 - b.1);
+    dx * dx + dy * dy
+}
+
+/// Component storage which buckets data into a uniform grid of cells.
+///
+/// Updating an entity's position is much cheaper than in a tree, which makes
+/// this a good fit for dense crowds and particle simulations where most entities
+/// move every frame. Queries are fastest when the cell size is roughly the
+/// radius they search within.
+///
+/// Positions changed through `get_mut` are picked up by the next mutable access
+/// to the storage.
+pub struct SpatialHashStorage<T: Component + Positioned2D> {
+    cell_size: f32,
+    data: HashMap<Entity, (T, Cell)>,
+    cells: HashMap<Cell, Vec<Entity>>,
+    // entities handed out mutably whose cell may be stale.
+    pending: HashSet<Entity>,
+}
+
+impl<T: Component + Positioned2D> SpatialHashStorage<T> {
+    /// Create a new storage with the given cell size.
+    pub fn new(cell_size: f32) -> Self {
+        assert!(cell_size > 0.0, "Cell size must be positive.");
+
+        SpatialHashStorage {
+            cell_size: cell_size,
+            data: HashMap::new(),
+            cells: HashMap::new(),
+            pending: HashSet::new(),
+        }
+    }
+
+    /// The side length of each cell.
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    /// Move all entities whose positions may have changed into their new cells.
+    pub fn reindex(&mut self) {
+        for e in ::std::mem::take(&mut self.pending) {
+            let (old, new) = match self.data.get(&e) {
+                Some(&(ref data, cell)) => (cell, self.cell(data.position())),
+                None => continue,
+            };
+
+            if old != new {
+                self.remove_from_cell(e, old);
+                self.cells.entry(new).or_default().push(e);
+                self.data.get_mut(&e).unwrap().1 = new;
+            }
+        }
+    }
+
+    /// All entities whose position lies within `radius` of `center`.
+    ///
+    /// Checks the cells within `radius` of `center`, or every occupied cell
+    /// if that's fewer.
+    pub fn neighbors(&self, center: (f32, f32), radius: f32) -> Vec<Entity> {
+        let r2 = radius * radius;
+        let (min, max) = (
+            self.cell((center.0 - radius, center.1 - radius)),
+            self.cell((center.0 + radius, center.1 + radius)),
+        );
+
+        let width = (max.0 as i64 - min.0 as i64 + 1).max(0) as u64;
+        let height = (max.1 as i64 - min.1 as i64 + 1).max(0) as u64;
+        let cells: Vec<&Vec<Entity>> = if width.saturating_mul(height) > self.cells.len() as u64 {
+            self.cells.iter().filter(|&(&(x, y), _)| {
+                x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1
+            }).map(|(_, es)| es).collect()
+        } else {
+            let mut cells = Vec::new();
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    if let Some(entities) = self.cells.get(&(x, y)) { cells.push(entities) }
+                }
+            }
+            cells
+        };
+
+        // pending entities may be in the wrong cell, so they're handled below.
+        let mut out: Vec<Entity> = cells.into_iter().flat_map(|entities| entities.iter().cloned()).filter(|e| {
+            !self.pending.contains(e) && distance_sq(center, self.position(e)) <= r2
+        }).collect();
+
+        out.extend(self.pending.iter().cloned().filter(|e| {
+            distance_sq(center, self.position(e)) <= r2
+        }));
+
+        out
+    }
+
+    /// Call the function with every pair of entities which lie within `radius`
+    /// of each other.
+    ///
+    /// Each unordered pair is visited exactly once. This is meant as the
+    /// broad phase for collision and flocking systems.
+    ///
+    /// Each occupied cell is compared against the cells within `radius` of it,
+    /// or against every other occupied cell if that's fewer. With a radius much
+    /// larger than the cell size that approaches comparing every pair, so the
+    /// cell size should be chosen with the radius in mind.
+    pub fn for_each_pair_within<F>(&mut self, radius: f32, mut f: F)
+    where F: FnMut(Entity, Entity) {
+        self.reindex();
+
+        let r2 = radius * radius;
+        let reach = (radius / self.cell_size).ceil() as i64;
+        // the cells after a cell which can hold entities within the radius of it.
+        let window = (reach as u64 + 1).saturating_mul(2 * reach as u64 + 1);
+        let scan_occupied = window > self.cells.len() as u64;
+
+        // whether the other cell is in range and comes after the cell, so each pair is seen once.
+        let after = |cell: Cell, other: Cell| {
+            let (dx, dy) = (other.0 as i64 - cell.0 as i64, other.1 as i64 - cell.1 as i64);
+            dx >= 0 && dx <= reach && dy.abs() <= reach && !(dx == 0 && dy <= 0)
+        };
+
+        for (&cell, entities) in &self.cells {
+            // pairs within the same cell.
+            for (i, &a) in entities.iter().enumerate() {
+                let pa = self.position(&a);
+                for &b in &entities[i + 1..] {
+                    if distance_sq(pa, self.position(&b)) <= r2 { f(a, b) }
+                }
+            }
+
+            // pairs with the neighboring cells.
+            let neighbors: Vec<&Vec<Entity>> = if scan_occupied {
+                self.cells.iter().filter(|&(&other, _)| after(cell, other)).map(|(_, es)| es).collect()
+            } else {
+                let mut neighbors = Vec::new();
+                for dx in 0..reach + 1 {
+                    for dy in -reach..reach + 1 {
+                        if dx == 0 && dy <= 0 { continue }
+
+                        let other = ((cell.0 as i64 + dx) as i32, (cell.1 as i64 + dy) as i32);
+                        if let Some(others) = self.cells.get(&other) { neighbors.push(others) }
+                    }
+                }
+                neighbors
+            };
+
+            for &a in entities {
+                let pa = self.position(&a);
+                for &b in neighbors.iter().flat_map(|others| others.iter()) {
+                    if distance_sq(pa, self.position(&b)) <= r2 { f(a, b) }
+                }
+            }
+        }
+    }
+
+    fn cell(&self, p: (f32, f32)) -> Cell {
+        ((p.0 / self.cell_size).floor() as i32, (p.1 / self.cell_size).floor() as i32)
+    }
+
+    fn position(&self, e: &Entity) -> (f32, f32) {
+        self.data[e].0.position()
+    }
+
+    fn remove_from_cell(&mut self, e: Entity, cell: Cell) {
+        let empty = match self.cells.get_mut(&cell) {
+            Some(entities) => {
+                entities.retain(|x| *x != e);
+                entities.is_empty()
+            }
+            None => false,
+        };
+
+        if empty { self.cells.remove(&cell); }
+    }
+}
+
+impl<T: Component + Positioned2D> Storage<T> for SpatialHashStorage<T> {
+    fn set(&mut self, e: VerifiedEntity, data: T) {
+        self.reindex();
+
+        let e = e.entity();
+        let cell = self.cell(data.position());
+        if let Some(old) = self.data.get(&e).map(|entry| entry.1) {
+            self.remove_from_cell(e, old);
+        }
+
+        self.cells.entry(cell).or_default().push(e);
+        self.data.insert(e, (data, cell));
+    }
+
+    fn has(&self, e: VerifiedEntity) -> bool {
+        self.data.contains_key(&e.entity())
+    }
+
+    fn get(&self, e: VerifiedEntity) -> Option<&T> {
+        self.data.get(&e.entity()).map(|entry| &entry.0)
+    }
+
+    fn get_mut(&mut self, e: VerifiedEntity) -> Option<&mut T> {
+        self.reindex();
+
+        let e = e.entity();
+        let entry = self.data.get_mut(&e)?;
+        self.pending.insert(e);
+        Some(&mut entry.0)
+    }
+
+    fn remove(&mut self, e: VerifiedEntity) -> Option<T> {
+        self.reindex();
+
+        let e = e.entity();
+        match self.data.remove(&e) {
+            Some((data, cell)) => {
+                self.remove_from_cell(e, cell);
+                Some(data)
+            }
+            None => None,
+        }
+    }
+
+    fn destroy(&mut self, e: Entity) {
+        if let Some((_, cell)) = self.data.remove(&e) {
+            self.remove_from_cell(e, cell);
+            self.pending.remove(&e);
+        }
+    }
+
+    fn entities<'a>(&'a self) -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(self.data.keys().cloned())
+    }
+}
+
+impl<T: Component + Positioned2D> RadiusIndex for SpatialHashStorage<T> {
+    fn within_radius(&self, center: (f32, f32), radius: f32) -> Vec<Entity> {
+        self.neighbors(center, radius)
+    }
+}
+
+impl<T: Component + Positioned2D> Default for SpatialHashStorage<T> {
+    fn default() -> Self {
+        SpatialHashStorage::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+/// A filter for entities whose position lies within a radius of a point.
+pub struct Neighbors<T> {
+    center: (f32, f32),
+    radius: f32,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Neighbors<T> {
+    /// Create a new filter for the circle with the given center and radius.
+    pub fn new(center: (f32, f32), radius: f32) -> Self {
+        Neighbors {
+            center: center,
+            radius: radius,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T> Filter for Neighbors<T>
+where T: Component<Storage=SpatialHashStorage<T>> + Positioned2D {
+    type Component = T;
+
+    fn pred(&self, storage: &SpatialHashStorage<T>, e: VerifiedEntity) -> bool {
+        storage.get(e).is_some_and(|data| {
+            distance_sq(self.center, data.position()) <= self.radius * self.radius
+        })
+    }
+
+    fn candidates<'a>(&'a self, storage: &'a SpatialHashStorage<T>)
+    -> Box<Iterator<Item=Entity> + 'a> {
+        Box::new(storage.neighbors(self.center, self.radius).into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct Particle(f32, f32);
+
+    impl Positioned2D for Particle {
+        fn position(&self) -> (f32, f32) { (self.0, self.1) }
+    }
+
+    impl Component for Particle {
+        type Storage = SpatialHashStorage<Particle>;
+    }
+
+    #[test]
+    fn pairs_and_neighbors() {
+        let mut manager = EntityManager::new();
+        let mut storage = SpatialHashStorage::new(1.0);
+
+        // a row of particles spaced 0.75 apart, crossing cell boundaries.
+        let entities: Vec<_> = (0..10).map(|i| {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Particle(i as f32 * 0.75 - 3.0, 0.5));
+            e
+        }).collect();
+
+        let mut pairs = Vec::new();
+        storage.for_each_pair_within(1.0, |a, b| pairs.push((a, b)));
+        assert_eq!(pairs.len(), 9);
+
+        let mut pairs = Vec::new();
+        storage.for_each_pair_within(1.5, |a, b| pairs.push((a, b)));
+        assert_eq!(pairs.len(), 17);
+
+        assert_eq!(storage.neighbors((0.0, 0.5), 0.8).len(), 3);
+
+        storage.get_mut(manager.verify(entities[0]).unwrap()).unwrap().0 = 0.1;
+        assert_eq!(Neighbors::<Particle>::new((0.0, 0.5), 0.8).candidates(&storage).count(), 4);
+    }
+
+    #[test]
+    fn pairs_with_large_radius() {
+        let mut manager = EntityManager::new();
+        let mut storage = SpatialHashStorage::new(1.0);
+
+        // scattered far more widely than the cells, so most of the window around a cell is empty.
+        let positions: Vec<(f32, f32)> = (0..40).map(|i| {
+            let i = i as f32;
+            ((i * 37.0) % 101.0 - 50.0, (i * 53.0) % 97.0 - 48.0)
+        }).collect();
+        for &p in &positions {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Particle(p.0, p.1));
+        }
+
+        for &radius in &[5.0, 30.0, 1000.0] {
+            let mut expected = 0;
+            for (i, &a) in positions.iter().enumerate() {
+                expected += positions[i + 1..].iter().filter(|&&b| distance_sq(a, b) <= radius * radius).count();
+            }
+
+            let mut pairs = 0;
+            storage.for_each_pair_within(radius, |_, _| pairs += 1);
+            assert_eq!(pairs, expected);
+        }
+    }
+
+    #[test]
+    fn neighbors_with_large_radius() {
+        let mut manager = EntityManager::new();
+        let mut storage = SpatialHashStorage::new(1.0);
+
+        for i in 0..10 {
+            let e = manager.next();
+            storage.set(manager.verify(e).unwrap(), Particle(i as f32 * 1000.0, -(i as f32) * 1000.0));
+        }
+
+        // these cover far more cells than are occupied, or more than fit in an `i32`.
+        assert_eq!(storage.neighbors((0.0, 0.0), 1.0e6).len(), 10);
+        assert_eq!(storage.neighbors((0.0, 0.0), 1.0e30).len(), 10);
+        assert_eq!(storage.neighbors((0.0, 0.0), f32::INFINITY).len(), 10);
+        assert_eq!(storage.neighbors((0.0, 0.0), 1500.0).len(), 2);
+    }
+}
@@ -0,0 +1,270 @@
+//! The positional index shared by the quadtree and octree storages.
+
+use std::collections::{HashMap, HashSet};
+
+use super::super::Entity;
+
+// maximum number of entries in a leaf before it is split.
+const NODE_CAPACITY: usize = 8;
+
+/// The bounds of a node in a tree which splits space into equal parts.
+pub trait Bounds: Copy {
+    /// A position within the space.
+    type Point: Copy;
+
+    /// Maximum depth of the tree. Leaves at this depth grow without bound.
+    const MAX_DEPTH: usize;
+
+    /// Whether the point lies within these bounds.
+    fn contains(&self, p: Self::Point) -> bool;
+
+    /// Split into equal parts, ordered so that `child_index()` indexes them.
+    fn split(&self) -> Vec<Self>;
+
+    /// The index of the part containing the point.
+    fn child_index(&self, p: Self::Point) -> usize;
+}
+
+struct Node<B: Bounds> {
+    bounds: B,
+    entries: Vec<(Entity, B::Point)>,
+    children: Option<Vec<Node<B>>>,
+}
+
+impl<B: Bounds> Node<B> {
+    fn new(bounds: B) -> Self {
+        Node {
+            bounds: bounds,
+            entries: Vec::new(),
+            children: None,
+        }
+    }
+
+    fn insert(&mut self, e: Entity, p: B::Point, depth: usize) {
+        if let Some(ref mut children) = self.children {
+            let idx = self.bounds.child_index(p);
+            return children[idx].insert(e, p, depth + 1);
+        }
+
+        self.entries.push((e, p));
+        if self.entries.len() > NODE_CAPACITY && depth < B::MAX_DEPTH {
+            let mut children: Vec<_> = self.bounds.split().into_iter().map(Node::new).collect();
+            for (e, p) in self.entries.drain(..) {
+                children[self.bounds.child_index(p)].insert(e, p, depth + 1);
+            }
+
+            self.children = Some(children);
+        }
+    }
+
+    // remove the entry for the entity, which must have been inserted at `p`.
+    // collapses children back into this node once they are sparse enough.
+    fn remove(&mut self, e: Entity, p: B::Point) -> bool {
+        let removed = match self.children {
+            Some(ref mut children) => children[self.bounds.child_index(p)].remove(e, p),
+            None => {
+                let pos = self.entries.iter().position(|&(x, _)| x == e);
+                if let Some(pos) = pos { self.entries.swap_remove(pos); }
+                return pos.is_some();
+            }
+        };
+
+        if removed { self.try_collapse() }
+        removed
+    }
+
+    // merge leaf children back into this node if they fit.
+    fn try_collapse(&mut self) {
+        let collapse = match self.children {
+            Some(ref children) => {
+                children.iter().all(|c| c.children.is_none()) &&
+                children.iter().map(|c| c.entries.len()).sum::<usize>() <= NODE_CAPACITY
+            }
+            None => false,
+        };
+
+        if collapse {
+            for child in self.children.take().unwrap().iter_mut() {
+                self.entries.append(&mut child.entries);
+            }
+        }
+    }
+
+    // move an entry within the tree. entries which stay within
+    // the same leaf only have their stored position updated.
+    fn relocate(&mut self, e: Entity, from: B::Point, to: B::Point, depth: usize) {
+        let (from_idx, to_idx) = (self.bounds.child_index(from), self.bounds.child_index(to));
+        match self.children {
+            Some(ref mut children) if from_idx == to_idx => {
+                return children[from_idx].relocate(e, from, to, depth + 1);
+            }
+            Some(_) => {}
+            None => {
+                for entry in self.entries.iter_mut().filter(|entry| entry.0 == e) {
+                    entry.1 = to;
+                }
+                return;
+            }
+        }
+
+        self.remove(e, from);
+        self.insert(e, to, depth);
+    }
+
+    // push all entries in nodes whose bounds pass `visit` and which themselves pass `test`.
+    fn query<V, P>(&self, visit: &V, test: &P, out: &mut Vec<Entity>)
+    where V: Fn(&B) -> bool, P: Fn(Entity, B::Point) -> bool {
+        if !visit(&self.bounds) { return }
+
+        out.extend(self.entries.iter().filter(|&&(e, p)| test(e, p)).map(|&(e, _)| e));
+        if let Some(ref children) = self.children {
+            for child in children.iter() {
+                child.query(visit, test, out);
+            }
+        }
+    }
+}
+
+// where an entity is indexed.
+#[derive(Clone, Copy)]
+enum Location<P> {
+    // in the tree, under this position.
+    Tree(P),
+    // possibly moved; the tree holds it under this now-stale position,
+    // unless it was outside of the bounds.
+    Pending(Option<P>),
+    // outside of the bounds of the tree.
+    Outside,
+}
+
+/// An index of entity positions in a tree covering fixed bounds.
+///
+/// Entities outside of the bounds are kept in a separate set and tested
+/// linearly by every query. Entities which may have moved are tested by their
+/// current position until the next `reindex()` puts them back into the tree.
+pub struct TreeIndex<B: Bounds> {
+    root: Node<B>,
+    locations: HashMap<Entity, Location<B::Point>>,
+    pending: Vec<Entity>,
+    outside: HashSet<Entity>,
+}
+
+impl<B: Bounds> TreeIndex<B> {
+    /// Create an empty index covering the given bounds.
+    pub fn new(bounds: B) -> Self {
+        TreeIndex {
+            root: Node::new(bounds),
+            locations: HashMap::new(),
+            pending: Vec::new(),
+            outside: HashSet::new(),
+        }
+    }
+
+    /// The bounds covered by the tree.
+    pub fn bounds(&self) -> B {
+        self.root.bounds
+    }
+
+    /// Index an entity at the given position, replacing any previous entry.
+    pub fn insert(&mut self, e: Entity, p: B::Point) {
+        self.remove(e);
+
+        let loc = if self.root.bounds.contains(p) {
+            self.root.insert(e, p, 0);
+            Location::Tree(p)
+        } else {
+            self.outside.insert(e);
+            Location::Outside
+        };
+
+        self.locations.insert(e, loc);
+    }
+
+    /// Remove an entity from the index.
+    pub fn remove(&mut self, e: Entity) {
+        match self.locations.remove(&e) {
+            Some(Location::Tree(p)) => { self.root.remove(e, p); }
+            Some(Location::Pending(p)) => {
+                if let Some(p) = p { self.root.remove(e, p); }
+                self.pending.retain(|x| *x != e);
+            }
+            Some(Location::Outside) => { self.outside.remove(&e); }
+            None => {}
+        }
+    }
+
+    /// Mark an entity as possibly moved, to be re-indexed by the next `reindex()`.
+    pub fn invalidate(&mut self, e: Entity) {
+        let loc = match self.locations.get_mut(&e) {
+            Some(loc) => loc,
+            None => return,
+        };
+
+        *loc = match *loc {
+            Location::Tree(p) => Location::Pending(Some(p)),
+            Location::Outside => {
+                self.outside.remove(&e);
+                Location::Pending(None)
+            }
+            Location::Pending(_) => return,
+        };
+
+        self.pending.push(e);
+    }
+
+    /// Re-index all entities which may have moved, given their current positions.
+    pub fn reindex<F>(&mut self, position: F)
+    where F: Fn(Entity) -> Option<B::Point> {
+        for e in ::std::mem::take(&mut self.pending) {
+            let (old, new) = match (self.locations.get(&e), position(e)) {
+                (Some(&Location::Pending(old)), Some(new)) => (old, new),
+                _ => continue,
+            };
+
+            let loc = if self.root.bounds.contains(new) {
+                match old {
+                    Some(old) => self.root.relocate(e, old, new, 0),
+                    None => self.root.insert(e, new, 0),
+                }
+                Location::Tree(new)
+            } else {
+                if let Some(old) = old { self.root.remove(e, old); }
+                self.outside.insert(e);
+                Location::Outside
+            };
+
+            self.locations.insert(e, loc);
+        }
+    }
+
+    /// All entities in nodes whose bounds pass `visit` and whose current position passes `test`.
+    pub fn query<V, P, F>(&self, visit: V, test: P, position: F) -> Vec<Entity>
+    where V: Fn(&B) -> bool, P: Fn(B::Point) -> bool, F: Fn(Entity) -> Option<B::Point> {
+        let mut out = Vec::new();
+
+        // pending entities are still in the tree under their old position,
+        // so they are skipped there and tested by their current position instead.
+        let locations = &self.locations;
+        self.root.query(&visit, &|e, p| {
+            test(p) && !matches!(locations.get(&e), Some(&Location::Pending(_)))
+        }, &mut out);
+
+        for &e in self.pending.iter().chain(self.outside.iter()) {
+            if position(e).is_some_and(&test) { out.push(e) }
+        }
+
+        out
+    }
+
+    /// Whether the entity is indexed as outside of the bounds.
+    #[cfg(test)]
+    pub fn is_outside(&self, e: Entity) -> bool {
+        self.outside.contains(&e)
+    }
+
+    /// Whether the tree has collapsed back into a single leaf.
+    #[cfg(test)]
+    pub fn is_collapsed(&self) -> bool {
+        self.root.children.is_none()
+    }
+}
@@ -0,0 +1,162 @@
+//! Cloning entities, and moving them between worlds.
+//!
+//! Every storage in the set is walked, so all of an entity's components come
+//! along. Cloning only copies components which implement `Component::duplicate`,
+//! while moving takes everything. Entity handles within the components are fixed
+//! up through `Component::map_entities`: handles to other entities in the same
+//! batch are mapped to their new counterparts.
+
+use std::collections::HashMap;
+
+use super::*;
+use super::set::Set;
+
+impl<S: Set> World<S> {
+    /// Copy an entity and its components into a new entity, returning it.
+    ///
+    /// Returns `None` if the entity is dead.
+    pub fn clone_entity(&mut self, e: Entity) -> Option<Entity> {
+        self.clone_entities(&[e]).get(&e).cloned()
+    }
+
+    /// Copy a batch of entities, returning the new entity for each living one.
+    ///
+    /// Handles to entities in the batch are mapped to their copies, and other
+    /// handles are kept. Cloning a subtree this way keeps its hierarchy intact,
+    /// and attaches the new root to the old root's parent.
+    pub fn clone_entities(&mut self, entities: &[Entity]) -> HashMap<Entity, Entity> {
+        let pairs = self.spawn_pairs(entities);
+        let map: HashMap<Entity, Entity> = pairs.iter().cloned().collect();
+
+        for &(from, to) in &pairs {
+            let from = self.entities.verify(from).unwrap();
+            let to = self.entities.verify(to).unwrap();
+            self.data.clone_entity(from, to, &mut |e| *map.get(&e).unwrap_or(&e));
+        }
+
+        map
+    }
+
+    /// Move an entity and its components into another world, returning its
+    /// handle there. The entity is despawned from this world.
+    ///
+    /// Returns `None` if the entity is dead.
+    pub fn transfer(&mut self, e: Entity, other: &mut World<S>) -> Option<Entity> {
+        self.transfer_entities(&[e], other).get(&e).cloned()
+    }
+
+    /// Move a batch of entities into another world, returning the handle
+    /// there for each living one.
+    ///
+    /// Handles to entities in the batch are mapped to their new handles, and
+    /// handles to anything else are mapped to a dead entity in the other world.
+    pub fn transfer_entities(&mut self, entities: &[Entity], other: &mut World<S>) -> HashMap<Entity, Entity> {
+        let mut pairs = Vec::new();
+        for &e in entities {
+            if self.entities.is_alive(e) && !pairs.iter().any(|&(from, _)| from == e) {
+                pairs.push((e, other.entities.next()));
+            }
+        }
+        let map: HashMap<Entity, Entity> = pairs.iter().cloned().collect();
+
+        let dead = other.entities.next();
+        other.entities.destroy(dead);
+
+        for &(from, to) in &pairs {
+            let from = self.entities.verify(from).unwrap();
+            let to = other.entities.verify(to).unwrap();
+            self.data.transfer(from, &mut other.data, to, &mut |e| *map.get(&e).unwrap_or(&dead));
+        }
+
+        // cleans up anything in this world still referring to the moved entities.
+        for &(from, _) in &pairs {
+            self.despawn(from);
+        }
+
+        map
+    }
+
+    // a new entity for each living entity given, in order.
+    fn spawn_pairs(&mut self, entities: &[Entity]) -> Vec<(Entity, Entity)> {
+        let mut pairs = Vec::new();
+        for &e in entities {
+            if self.entities.is_alive(e) && !pairs.iter().any(|&(from, _)| from == e) {
+                pairs.push((e, self.entities.next()));
+            }
+        }
+
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::hierarchy::Parent;
+
+    #[derive(Debug, Clone, PartialEq)]
+    struct Health(u32);
+
+    impl Component for Health {
+        type Storage = DefaultStorage<Self>;
+
+        fn duplicate(&self) -> Option<Self> { Some(self.clone()) }
+    }
+
+    // can't be duplicated, but can still be moved.
+    struct Name(&'static str);
+    impl Component for Name { type Storage = DefaultStorage<Self>; }
+
+    component_set! {
+        struct TestSet { Health, Name, Parent }
+    }
+
+    fn health(world: &World<TestSet>, e: Entity) -> Option<u32> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Health>().get(e).map(|h| h.0)
+    }
+
+    fn name(world: &World<TestSet>, e: Entity) -> Option<&'static str> {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<Name>().get(e).map(|n| n.0)
+    }
+
+    #[test]
+    fn clone_and_transfer() {
+        let mut world = World::new(TestSet::new());
+        let root = world.build_entity().with(Health(1)).spawn();
+        let a = world.build_entity().with(Health(10)).with(Name("a")).spawn();
+        let b = world.build_entity().with(Health(20)).spawn();
+        world.set_parent(a, root).unwrap();
+        world.set_parent(b, a).unwrap();
+
+        let a2 = world.clone_entity(a).unwrap();
+        assert_eq!(health(&world, a2), Some(10));
+        assert_eq!(name(&world, a2), None);
+        assert_eq!(world.parent(a2), Some(root));
+        assert_eq!(world.children(root), vec![a, a2]);
+
+        // the cloned subtree refers to itself.
+        let map = world.clone_entities(&[a, b]);
+        let (a3, b3) = (map[&a], map[&b]);
+        assert_eq!(world.children(a3), vec![b3]);
+        assert_eq!(world.parent(b3), Some(a3));
+        assert_eq!(world.parent(a3), Some(root));
+        assert_eq!(world.children(root), vec![a, a2, a3]);
+
+        let mut other = World::new(TestSet::new());
+        let map = world.transfer_entities(&[a, b], &mut other);
+        let (a4, b4) = (map[&a], map[&b]);
+        assert!(!world.entities.is_alive(a) && !world.entities.is_alive(b));
+        assert_eq!(world.children(root), vec![a2, a3]);
+
+        assert_eq!(health(&other, a4), Some(10));
+        assert_eq!(name(&other, a4), Some("a"));
+        assert_eq!(other.children(a4), vec![b4]);
+        // the old root isn't in the other world.
+        assert_eq!(other.parent(a4).map(|p| other.entities.is_alive(p)), Some(false));
+
+        assert_eq!(world.clone_entity(a), None);
+        assert_eq!(world.transfer(b, &mut other), None);
+    }
+}
@@ -0,0 +1,334 @@
+//! Local and global transforms, and the system which propagates them.
+//!
+//! Entities are positioned relative to their parent with a `Transform`.
+//! `TransformSystem` computes the `GlobalTransform` of every entity with a
+//! `Transform` from the top of the hierarchy down. The world's set must contain
+//! `Transform`, `GlobalTransform`, and `Parent`.
+
+use std::collections::HashMap;
+use std::hash::Hasher;
+
+use super::*;
+use super::hierarchy::{Parent, ParentStorage};
+use super::set::Set;
+
+/// A column-major 4x4 matrix.
+pub type Matrix = [[f32; 4]; 4];
+
+/// The identity matrix.
+pub const IDENTITY: Matrix = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// Multiply two column-major matrices, applying `b` before `a`.
+pub fn mul(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut out = [[0.0; 4]; 4];
+    for col in 0..4 {
+        for row in 0..4 {
+            out[col][row] = (0..4).map(|k| a[k][row] * b[col][k]).sum();
+        }
+    }
+
+    out
+}
+
+/// The position, rotation, and scale of an entity relative to its parent.
+///
+/// Entities without a parent are relative to the origin.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// Translation along each axis.
+    pub translation: (f32, f32, f32),
+    /// Rotation as a unit quaternion, `(x, y, z, w)`.
+    pub rotation: (f32, f32, f32, f32),
+    /// Scale along each axis.
+    pub scale: (f32, f32, f32),
+}
+
+impl Transform {
+    /// A transform at the given position, with no rotation and unit scale.
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self {
+        Transform {
+            translation: (x, y, z),
+            ..Transform::default()
+        }
+    }
+
+    /// A 2D transform: a position in the XY plane and a rotation
+    /// about the Z axis, in radians.
+    pub fn from_2d(x: f32, y: f32, angle: f32) -> Self {
+        let (sin, cos) = (angle / 2.0).sin_cos();
+        Transform {
+            translation: (x, y, 0.0),
+            rotation: (0.0, 0.0, sin, cos),
+            ..Transform::default()
+        }
+    }
+
+    /// The matrix for this transform: scale, then rotate, then translate.
+    pub fn matrix(&self) -> Matrix {
+        let (x, y, z, w) = self.rotation;
+        let (sx, sy, sz) = self.scale;
+        let (tx, ty, tz) = self.translation;
+
+        [
+            [(1.0 - 2.0 * (y * y + z * z)) * sx, 2.0 * (x * y + w * z) * sx, 2.0 * (x * z - w * y) * sx, 0.0],
+            [2.0 * (x * y - w * z) * sy, (1.0 - 2.0 * (x * x + z * z)) * sy, 2.0 * (y * z + w * x) * sy, 0.0],
+            [2.0 * (x * z + w * y) * sz, 2.0 * (y * z - w * x) * sz, (1.0 - 2.0 * (x * x + y * y)) * sz, 0.0],
+            [tx, ty, tz, 1.0],
+        ]
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: (0.0, 0.0, 0.0),
+            rotation: (0.0, 0.0, 0.0, 1.0),
+            scale: (1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Component for Transform {
+    type Storage = DefaultStorage<Self>;
+
+    fn duplicate(&self) -> Option<Self> { Some(*self) }
+
+    fn hash_state(&self, state: &mut Hasher) -> bool {
+        let (t, r, s) = (self.translation, self.rotation, self.scale);
+        for &x in &[t.0, t.1, t.2, r.0, r.1, r.2, r.3, s.0, s.1, s.2] {
+            state.write_u32(x.to_bits());
+        }
+        true
+    }
+}
+
+/// The world matrix of an entity, computed by `TransformSystem`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlobalTransform(pub Matrix);
+
+impl GlobalTransform {
+    /// The position of the entity in world space.
+    pub fn translation(&self) -> (f32, f32, f32) {
+        (self.0[3][0], self.0[3][1], self.0[3][2])
+    }
+}
+
+impl Default for GlobalTransform {
+    fn default() -> Self {
+        GlobalTransform(IDENTITY)
+    }
+}
+
+impl Component for GlobalTransform {
+    type Storage = DefaultStorage<Self>;
+
+    fn duplicate(&self) -> Option<Self> { Some(*self) }
+
+    fn hash_state(&self, state: &mut Hasher) -> bool {
+        for column in &self.0 {
+            for &x in column {
+                state.write_u32(x.to_bits());
+            }
+        }
+        true
+    }
+}
+
+/// Computes `GlobalTransform`s from `Transform`s and the hierarchy.
+///
+/// The local transform of each entity is remembered between runs, and only
+/// subtrees where a local transform changed (or which have been attached somewhere
+/// new) are recomputed. Independent roots are processed in parallel.
+///
+/// Children without a `Transform` are skipped, along with their descendants.
+#[derive(Default)]
+pub struct TransformSystem {
+    // local transforms and parents as of the last run.
+    last: HashMap<Entity, (Transform, Option<Entity>)>,
+}
+
+impl TransformSystem {
+    /// Create the system.
+    pub fn new() -> Self {
+        TransformSystem::default()
+    }
+}
+
+// read-only view of the data, shared between threads while computing.
+struct Context<'a> {
+    entities: &'a EntityManager,
+    transforms: &'a DefaultStorage<Transform>,
+    globals: &'a DefaultStorage<GlobalTransform>,
+    parents: &'a ParentStorage,
+    last: &'a HashMap<Entity, (Transform, Option<Entity>)>,
+}
+
+// an entity whose global transform was recomputed.
+struct Update {
+    entity: Entity,
+    parent: Option<Entity>,
+    local: Transform,
+    global: Matrix,
+}
+
+impl<'a> Context<'a> {
+    fn propagate_roots(&self, roots: &[Entity], out: &mut Vec<Update>) {
+        // split the roots in half until there's only one left on each thread.
+        if roots.len() > 1 {
+            let (left, right) = roots.split_at(roots.len() / 2);
+            let (mut left_out, mut right_out) = (Vec::new(), Vec::new());
+            ::rayon::join(
+                || self.propagate_roots(left, &mut left_out),
+                || self.propagate_roots(right, &mut right_out),
+            );
+
+            out.extend(left_out);
+            out.extend(right_out);
+        } else if let Some(&root) = roots.first() {
+            self.propagate(root, None, &IDENTITY, false, out);
+        }
+    }
+
+    fn propagate(&self, e: Entity, parent: Option<Entity>, parent_global: &Matrix,
+                 parent_dirty: bool, out: &mut Vec<Update>) {
+        let v = match self.entities.verify(e) {
+            Some(v) => v,
+            None => return,
+        };
+
+        let local = match self.transforms.get(v) {
+            Some(local) => *local,
+            None => return,
+        };
+
+        let current = self.globals.get(v);
+        let dirty = parent_dirty || current.is_none() || self.last.get(&e) != Some(&(local, parent));
+
+        let global = if dirty {
+            let global = mul(parent_global, &local.matrix());
+            out.push(Update {
+                entity: e,
+                parent: parent,
+                local: local,
+                global: global,
+            });
+            global
+        } else {
+            current.unwrap().0
+        };
+
+        for child in self.parents.children(e) {
+            self.propagate(child, Some(e), &global, dirty, out);
+        }
+    }
+}
+
+impl System for TransformSystem {
+    fn process<'a, S: 'a + Set>(&mut self, wh: WorldHandle<'a, S>) {
+        let transforms = wh.data.lock_storage::<Transform>();
+        let parents = wh.data.lock_storage::<Parent>();
+        let mut globals = wh.data.lock_storage::<GlobalTransform>();
+
+        // roots are entities whose parent doesn't have a transform to be relative to.
+        let roots: Vec<Entity> = transforms.entities().filter(|&e| {
+            let v = match wh.entities.verify(e) {
+                Some(v) => v,
+                None => return false,
+            };
+
+            match parents.get(v).and_then(|p| wh.entities.verify(p.0)) {
+                Some(parent) => !transforms.has(parent),
+                None => true,
+            }
+        }).collect();
+
+        let mut updates = Vec::new();
+        Context {
+            entities: wh.entities,
+            transforms: &transforms,
+            globals: &globals,
+            parents: &parents,
+            last: &self.last,
+        }.propagate_roots(&roots, &mut updates);
+
+        for update in updates {
+            if let Some(v) = wh.entities.verify(update.entity) {
+                globals.set(v, GlobalTransform(update.global));
+            }
+
+            self.last.insert(update.entity, (update.local, update.parent));
+        }
+
+        // forget entities which are gone or no longer have a transform.
+        self.last.retain(|&e, _| match wh.entities.verify(e) {
+            Some(v) => transforms.has(v),
+            None => false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    component_set! {
+        struct TestSet { Transform, GlobalTransform, Parent }
+    }
+
+    fn position(world: &World<TestSet>, e: Entity) -> (f32, f32, f32) {
+        let e = world.entities.verify(e).unwrap();
+        world.data.lock_storage::<GlobalTransform>().get(e).unwrap().translation()
+    }
+
+    fn close(a: (f32, f32, f32), b: (f32, f32, f32)) -> bool {
+        (a.0 - b.0).abs() < 1e-5 && (a.1 - b.1).abs() < 1e-5 && (a.2 - b.2).abs() < 1e-5
+    }
+
+    #[test]
+    fn propagation() {
+        use std::f32::consts::PI;
+
+        let mut world = World::new(TestSet::new());
+        let mut system = TransformSystem::new();
+
+        let root = world.build_entity().with(Transform::from_2d(10.0, 0.0, PI / 2.0)).spawn();
+        let child = world.build_entity().with(Transform::from_translation(1.0, 0.0, 0.0)).spawn();
+        let other = world.build_entity().with(Transform::from_translation(0.0, 5.0, 0.0)).spawn();
+        world.set_parent(child, root).unwrap();
+
+        system.process(world.handle());
+        assert!(close(position(&world, root), (10.0, 0.0, 0.0)));
+        assert!(close(position(&world, child), (10.0, 1.0, 0.0)));
+        assert!(close(position(&world, other), (0.0, 5.0, 0.0)));
+
+        // unchanged subtrees aren't recomputed, so an overwritten global sticks.
+        {
+            let other_v = world.entities.verify(other).unwrap();
+            world.data.get_storage_mut::<GlobalTransform>().set(other_v, GlobalTransform(IDENTITY));
+            let root_v = world.entities.verify(root).unwrap();
+            world.data.get_storage_mut::<Transform>().get_mut(root_v).unwrap().translation.0 = 20.0;
+        }
+
+        system.process(world.handle());
+        assert!(close(position(&world, child), (20.0, 1.0, 0.0)));
+        assert!(close(position(&world, other), (0.0, 0.0, 0.0)));
+
+        // reparenting counts as a change.
+        world.set_parent(other, child).unwrap();
+        system.process(world.handle());
+        assert!(close(position(&world, other), (15.0, 1.0, 0.0)));
+
+        // as does a parent set without going through the world.
+        let grandchild = world.build_entity()
+            .with(Transform::from_translation(0.0, 2.0, 0.0))
+            .with(Parent(other))
+            .spawn();
+        system.process(world.handle());
+        assert!(close(position(&world, grandchild), (13.0, 1.0, 0.0)));
+    }
+}
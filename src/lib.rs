@@ -1,7 +1,9 @@
-#![feature(specialization)]
-
+#[cfg(feature = "window")]
 extern crate glutin;
+#[cfg(feature = "window")]
 extern crate gl;
 extern crate rayon;
 
-pub mod ecs;
\ No newline at end of file
+#[macro_use]
+pub mod ecs;
+pub mod app;